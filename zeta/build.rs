@@ -3,20 +3,90 @@ use std::{env, fs, path::Path};
 use typify::{TypeSpace, TypeSpaceSettings};
 
 fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=ZETA_SCHEMA_REFRESH");
+
     let out_dir = env::var("OUT_DIR").unwrap();
-    for (url, name) in &[
-        ("https://zed.dev/schema/themes/v0.1.0.json", "themes-v1"),
-        ("https://zed.dev/schema/themes/v0.2.0.json", "themes-v2"),
+    let refresh = env::var("ZETA_SCHEMA_REFRESH").is_ok_and(|value| value == "1");
+    println!("cargo:rustc-check-cfg=cfg(themes_v3)");
+    // Watch the whole directory rather than each schema: Cargo treats a watched file that doesn't
+    // exist (like the optional v3 schema) as changed, which would rerun this script on every build.
+    if Path::new("schemas").is_dir() {
+        println!("cargo:rerun-if-changed=schemas");
+    }
+    // The v0.3.0 schema may not be published yet, so `Theme::V3` is only compiled in (behind the
+    // `themes_v3` cfg) when the schema can be found.
+    for (url, name, required) in &[
+        (
+            "https://zed.dev/schema/themes/v0.1.0.json",
+            "themes-v1",
+            true,
+        ),
+        (
+            "https://zed.dev/schema/themes/v0.2.0.json",
+            "themes-v2",
+            true,
+        ),
+        (
+            "https://zed.dev/schema/themes/v0.3.0.json",
+            "themes-v3",
+            false,
+        ),
     ] {
-        let schema = get_schema(url);
+        let Some(schema) = get_schema(url, name, &out_dir, refresh) else {
+            assert!(
+                !required,
+                "no bundled schema for {url}; run scripts/update-schemas.sh or build with \
+                 ZETA_SCHEMA_REFRESH=1"
+            );
+            println!("cargo:warning=no schema for {url}, building without {name}");
+            continue;
+        };
         let rust = schema_to_rust(schema);
         fs::write(Path::new(&out_dir).join(format!("{name}.rs")), rust).unwrap();
+        if *name == "themes-v3" {
+            println!("cargo:rustc-cfg=themes_v3");
+        }
     }
 }
 
-fn get_schema(url: &str) -> schemars::schema::RootSchema {
-    serde_json::from_str::<schemars::schema::RootSchema>(&get(url).unwrap().text().unwrap())
-        .unwrap()
+/// Get a schema from the bundled copy in `schemas/<name>.json`. The network is only hit when
+/// `refresh` is set (`ZETA_SCHEMA_REFRESH=1`), in which case the fetched schema is used, falling
+/// back to the bundled copy if the request fails. Returns `None` if the schema can't be found
+/// anywhere.
+///
+/// The schema used is copied to `$OUT_DIR/<name>.schema.json` for tests. Fetched schemas are never
+/// written to `schemas/`; `scripts/update-schemas.sh` does that.
+fn get_schema(
+    url: &str,
+    name: &str,
+    out_dir: &str,
+    refresh: bool,
+) -> Option<schemars::schema::RootSchema> {
+    let bundled_path = Path::new("schemas").join(format!("{name}.json"));
+    let fetched = if refresh {
+        get(url)
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .and_then(reqwest::blocking::Response::text)
+            .map_err(|e| {
+                println!("cargo:warning=failed to fetch {url}, using bundled schema: {e}");
+            })
+            .ok()
+    } else {
+        None
+    };
+
+    let text = match fetched {
+        Some(text) => text,
+        None => fs::read_to_string(&bundled_path).ok()?,
+    };
+    fs::write(
+        Path::new(out_dir).join(format!("{name}.schema.json")),
+        &text,
+    )
+    .unwrap();
+
+    Some(serde_json::from_str::<schemars::schema::RootSchema>(&text).unwrap())
 }
 
 fn schema_to_rust(schema: schemars::schema::RootSchema) -> String {
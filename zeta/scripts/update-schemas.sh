@@ -0,0 +1,21 @@
+#!/usr/bin/env sh
+# Download the Zed theme schemas into `schemas/`, which the build script generates the theme types
+# from. Commit the results to keep builds offline and deterministic.
+set -eu
+
+cd "$(dirname "$0")/.."
+mkdir -p schemas
+
+for version in 1 2 3; do
+    url="https://zed.dev/schema/themes/v0.$version.0.json"
+    if curl --fail --silent --show-error --location "$url" --output "schemas/themes-v$version.json.tmp"; then
+        mv "schemas/themes-v$version.json.tmp" "schemas/themes-v$version.json"
+    else
+        rm -f "schemas/themes-v$version.json.tmp"
+        # Only v0.3.0 is optional, since it may not be published yet.
+        if [ "$version" != 3 ]; then
+            exit 1
+        fi
+        echo "skipping $url, which may not be published yet" >&2
+    fi
+done
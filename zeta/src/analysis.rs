@@ -0,0 +1,953 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+    time::Instant,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use log::{debug, warn};
+use regex::Regex;
+use serde::Serialize;
+use streaming_iterator::StreamingIterator;
+use tree_sitter::QueryCursor;
+
+use crate::types::{
+    Appearance, Extension, ExtensionMetadata, ExtensionType, Language, LanguageExtension,
+    QueryCaptures, Theme,
+};
+
+/// Query file of a [`Language`] to analyze.
+#[derive(Debug, Clone, ValueEnum)]
+pub enum QueryKind {
+    Highlights,
+    Injections,
+    Folds,
+    Outline,
+    Brackets,
+}
+
+impl QueryKind {
+    pub fn source<'a>(&self, language: &'a Language) -> Option<&'a String> {
+        match self {
+            QueryKind::Highlights => language.highlights_queries.as_ref(),
+            QueryKind::Injections => language.injections_queries.as_ref(),
+            QueryKind::Folds => language.folds_queries.as_ref(),
+            QueryKind::Outline => language.outline_queries.as_ref(),
+            QueryKind::Brackets => language.brackets_queries.as_ref(),
+        }
+    }
+
+    /// Captures of this query file stored by the scan, if any.
+    pub fn stored_captures<'a>(&self, language: &'a Language) -> Option<&'a Vec<String>> {
+        let captures = language.captures.as_ref()?;
+        match self {
+            QueryKind::Highlights => captures.highlights.as_ref(),
+            QueryKind::Injections => captures.injections.as_ref(),
+            QueryKind::Folds => captures.folds.as_ref(),
+            QueryKind::Outline => captures.outline.as_ref(),
+            QueryKind::Brackets => captures.brackets.as_ref(),
+        }
+    }
+}
+
+/// Extract and store the captures of every query file, for the languages that don't have them yet.
+pub fn store_query_captures(language_extension: &mut LanguageExtension) {
+    let (mut ts_parser, ts_query) = capture_query();
+    let mut cursor = QueryCursor::new();
+
+    for language in &mut language_extension.languages {
+        if language.captures.is_some() {
+            continue;
+        }
+
+        let mut extract = |source: &Option<String>| {
+            source.as_ref().and_then(|source| {
+                extract_capture_names(source, &mut ts_parser, &ts_query, &mut cursor).ok()
+            })
+        };
+        language.captures = Some(QueryCaptures {
+            highlights: extract(&language.highlights_queries),
+            injections: extract(&language.injections_queries),
+            folds: extract(&language.folds_queries),
+            outline: extract(&language.outline_queries),
+            brackets: extract(&language.brackets_queries),
+        });
+    }
+}
+
+/// Build a tree-sitter-query parser and a query matching capture names in query files.
+pub fn capture_query() -> (tree_sitter::Parser, tree_sitter::Query) {
+    let mut ts_parser = tree_sitter::Parser::new();
+    ts_parser
+        .set_language(&tree_sitter_query::LANGUAGE.into())
+        .expect("should load tree-sitter-query grammar");
+
+    let ts_query = tree_sitter::Query::new(
+        &tree_sitter_query::LANGUAGE.into(),
+        "(capture (identifier) @name)",
+    )
+    .expect("tree-sitter-query capture query should build");
+
+    (ts_parser, ts_query)
+}
+
+/// Extract every capture name (including repeats) from a query file. The cursor is reset by each
+/// call, so a single cursor can be reused across files.
+///
+/// Fails if the query file doesn't parse cleanly, or if a capture name isn't valid UTF-8.
+pub fn extract_capture_names(
+    source_code: &str,
+    ts_parser: &mut tree_sitter::Parser,
+    ts_query: &tree_sitter::Query,
+    cursor: &mut QueryCursor,
+) -> Result<Vec<String>> {
+    let tree = ts_parser
+        .parse(source_code, None)
+        .context("failed to parse query file")?;
+    if tree.root_node().has_error() {
+        match first_error_line(&tree) {
+            Some(line) => bail!("query file has a syntax error on line {line}"),
+            None => bail!("query file has a syntax error"),
+        }
+    }
+    let text_bytes = source_code.as_bytes();
+    let mut captures = cursor.captures(ts_query, tree.root_node(), text_bytes);
+
+    let mut capture_names: Vec<String> = Vec::new();
+    while let Some((c, _)) = captures.next() {
+        for capture in c.captures {
+            capture_names.push(capture.node.utf8_text(text_bytes)?.to_string());
+        }
+    }
+
+    Ok(capture_names)
+}
+
+/// Line (1-based) of the first ERROR or MISSING node in a tree, if any.
+fn first_error_line(tree: &tree_sitter::Tree) -> Option<usize> {
+    if !tree.root_node().has_error() {
+        return None;
+    }
+
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        if node.is_error() || node.is_missing() {
+            return Some(node.start_position().row + 1);
+        }
+
+        // Only descend into subtrees containing errors.
+        if node.has_error() && cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return None;
+            }
+        }
+    }
+}
+
+/// Highlight queries that don't parse cleanly, as the extension id, language name, and approximate
+/// line of the first parse error.
+pub fn highlights_parse_errors(extensions: &[Extension]) -> Vec<(String, String, usize)> {
+    let (mut ts_parser, _) = capture_query();
+    let mut parse_errors: Vec<(String, String, usize)> = Vec::new();
+
+    for extension in extensions {
+        if let ExtensionType::Language(language_extension) = &extension.r#type {
+            for language in &language_extension.languages {
+                let Some(highlights) = &language.highlights_queries else {
+                    continue;
+                };
+
+                if let Some(line) = ts_parser
+                    .parse(highlights, None)
+                    .and_then(|tree| first_error_line(&tree))
+                {
+                    parse_errors.push((extension.id.clone(), language.config.name.clone(), line));
+                }
+            }
+        }
+    }
+
+    parse_errors
+}
+
+/// Captures used in the `kind` query files of each language extension, keyed by extension id.
+/// Captures are not deduplicated, and private (`_`-prefixed) captures are skipped unless
+/// `include_private` is set.
+pub fn captures_by_language(
+    extensions: &[Extension],
+    kind: &QueryKind,
+    include_private: bool,
+) -> HashMap<String, Vec<String>> {
+    let (mut ts_parser, ts_query) = capture_query();
+    let mut cursor = QueryCursor::new();
+    let mut captures_by_language: HashMap<String, Vec<String>> = HashMap::new();
+
+    let started = Instant::now();
+
+    for extension in extensions {
+        if let ExtensionType::Language(language_extension) = &extension.r#type {
+            let captures: Vec<String> = language_extension
+                .languages
+                .iter()
+                .filter_map(|language| {
+                    if let Some(captures) = kind.stored_captures(language) {
+                        return Some(captures.clone());
+                    }
+
+                    let queries = kind.source(language)?;
+                    extract_capture_names(queries, &mut ts_parser, &ts_query, &mut cursor)
+                        .map_err(|error| {
+                            warn!(
+                                "skipping {kind:?} queries of '{}' in '{}': {error:#}",
+                                language.config.name, extension.id
+                            );
+                        })
+                        .ok()
+                })
+                .flatten()
+                .filter(|capture| include_private || !capture.starts_with('_'))
+                .collect();
+
+            captures_by_language.insert(extension.id.clone(), captures);
+        }
+    }
+    debug!(
+        "extracted {kind:?} captures of {} languages in {:?}",
+        captures_by_language.len(),
+        started.elapsed()
+    );
+
+    captures_by_language
+}
+
+/// Core captures of outline queries, used by Zed's symbol picker.
+pub const OUTLINE_CORE_CAPTURES: [&str; 3] = ["name", "item", "context"];
+
+/// Languages shipping an outline query that uses none of the [`OUTLINE_CORE_CAPTURES`], as the
+/// extension id and language name.
+pub fn outline_queries_without_core_captures(extensions: &[Extension]) -> Vec<(String, String)> {
+    let (mut ts_parser, ts_query) = capture_query();
+    let mut cursor = QueryCursor::new();
+    let mut languages: Vec<(String, String)> = Vec::new();
+
+    for extension in extensions {
+        if let ExtensionType::Language(language_extension) = &extension.r#type {
+            for language in &language_extension.languages {
+                let Some(outline) = &language.outline_queries else {
+                    continue;
+                };
+
+                let captures =
+                    match extract_capture_names(outline, &mut ts_parser, &ts_query, &mut cursor) {
+                        Ok(captures) => captures,
+                        Err(error) => {
+                            warn!(
+                                "skipping outline queries of '{}' in '{}': {error:#}",
+                                language.config.name, extension.id
+                            );
+                            continue;
+                        }
+                    };
+                if !captures
+                    .iter()
+                    .any(|capture| OUTLINE_CORE_CAPTURES.contains(&capture.as_str()))
+                {
+                    languages.push((extension.id.clone(), language.config.name.clone()));
+                }
+            }
+        }
+    }
+
+    languages.sort_unstable();
+    languages
+}
+
+/// Capture tables derived from scanned extensions, shared by the analysis queries.
+#[derive(Debug, Serialize)]
+pub struct CaptureTables {
+    /// See [`captures_by_language`].
+    pub captures_by_language: HashMap<String, Vec<String>>,
+    /// See [`supported_captures_by_theme`].
+    pub supported_captures_by_theme: HashMap<String, Vec<String>>,
+}
+
+impl CaptureTables {
+    /// Build the capture tables, collecting language captures from the `kind` query files.
+    pub fn new(extensions: &[Extension], kind: &QueryKind, include_private: bool) -> Self {
+        CaptureTables {
+            captures_by_language: captures_by_language(extensions, kind, include_private),
+            supported_captures_by_theme: supported_captures_by_theme(extensions),
+        }
+    }
+}
+
+/// Sorted, deduplicated syntax captures supported by each theme extension, keyed by extension id.
+pub fn supported_captures_by_theme(extensions: &[Extension]) -> HashMap<String, Vec<String>> {
+    extensions
+        .iter()
+        .filter_map(|extension| match &extension.r#type {
+            ExtensionType::Theme(theme_extension) => {
+                Some((extension.id.clone(), theme_extension.syntax_capture_names()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Paths of the theme files that couldn't be parsed, for each theme extension with at least one.
+pub fn invalid_themes(extensions: &[Extension]) -> Vec<(String, Vec<PathBuf>)> {
+    extensions
+        .iter()
+        .filter_map(|extension| match &extension.r#type {
+            ExtensionType::Theme(theme_extension) => {
+                let invalid_theme_paths: Vec<PathBuf> = theme_extension
+                    .themes
+                    .iter()
+                    .filter_map(|theme| match theme {
+                        Some(Theme::Invalid(path)) => Some(path.clone()),
+                        _ => None,
+                    })
+                    .collect();
+
+                if invalid_theme_paths.is_empty() {
+                    None
+                } else {
+                    Some((extension.id.clone(), invalid_theme_paths))
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// All captures used by at least one language extension.
+pub fn used_captures(captures_by_language: &HashMap<String, Vec<String>>) -> HashSet<String> {
+    captures_by_language.values().flatten().cloned().collect()
+}
+
+/// Rough score of how well themes support each language: `7 * depth / captures + 3 * breadth`,
+/// where depth is the total number of themes supporting each capture of the language and breadth is
+/// the number of themes supporting at least one of them. Languages without captures have no depth.
+pub fn language_theme_support_scores(
+    captures_by_language: &HashMap<String, Vec<String>>,
+    supported_captures_by_theme: &HashMap<String, Vec<String>>,
+) -> HashMap<String, usize> {
+    captures_by_language
+        .iter()
+        .map(|(language, captures)| {
+            let capture_support_depth: usize = captures
+                .iter()
+                .map(|capture| {
+                    supported_captures_by_theme
+                        .values()
+                        .filter(|captures| captures.contains(capture))
+                        .count()
+                })
+                .sum();
+
+            let theme_support_breadth = supported_captures_by_theme
+                .values()
+                .filter(|supported_captures| {
+                    supported_captures
+                        .iter()
+                        .any(|capture| captures.contains(capture))
+                })
+                .count();
+
+            let scaled_capture_support_depth = if captures.is_empty() {
+                0
+            } else {
+                7 * capture_support_depth / captures.len()
+            };
+
+            (
+                language.clone(),
+                scaled_capture_support_depth + 3 * theme_support_breadth,
+            )
+        })
+        .collect()
+}
+
+/// Syntax keys of each theme extension that match no capture used by a language, as the extension
+/// id and its sorted unknown keys, sorted by id. A key also matches the captures it's a prefix of
+/// (e.g. `function` matches `function.method`), since Zed falls back to less specific keys.
+pub fn unknown_theme_keys(
+    supported_captures_by_theme: &HashMap<String, Vec<String>>,
+    used_captures: &HashSet<String>,
+) -> Vec<(String, Vec<String>)> {
+    let mut unknown_keys: Vec<(String, Vec<String>)> = supported_captures_by_theme
+        .iter()
+        .filter_map(|(theme, keys)| {
+            let unknown: Vec<String> = keys
+                .iter()
+                .filter(|key| {
+                    !used_captures.contains(*key)
+                        && !used_captures.iter().any(|capture| {
+                            capture
+                                .strip_prefix(key.as_str())
+                                .is_some_and(|rest| rest.starts_with('.'))
+                        })
+                })
+                .cloned()
+                .collect();
+            (!unknown.is_empty()).then(|| (theme.clone(), unknown))
+        })
+        .collect();
+
+    unknown_keys.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    unknown_keys
+}
+
+/// All captures supported by at least one theme extension.
+pub fn themed_captures(
+    supported_captures_by_theme: &HashMap<String, Vec<String>>,
+) -> HashSet<String> {
+    supported_captures_by_theme
+        .values()
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+/// Count the number of languages using each capture, counting each capture at most once per
+/// language.
+pub fn count_capture_usage(
+    captures_by_language: &HashMap<String, Vec<String>>,
+) -> HashMap<String, usize> {
+    let mut capture_counts: HashMap<String, usize> = HashMap::new();
+
+    for captures in captures_by_language.values() {
+        let unique_captures: HashSet<&String> = captures.iter().collect();
+        for capture in unique_captures {
+            *capture_counts.entry(capture.clone()).or_default() += 1;
+        }
+    }
+
+    capture_counts
+}
+
+/// Count the number of distinct captures used by each language.
+pub fn distinct_capture_counts(
+    captures_by_language: &HashMap<String, Vec<String>>,
+) -> HashMap<String, usize> {
+    captures_by_language
+        .iter()
+        .map(|(language, captures)| {
+            let unique_captures: HashSet<&String> = captures.iter().collect();
+            (language.clone(), unique_captures.len())
+        })
+        .collect()
+}
+
+/// Summary statistics over the number of distinct captures used per language.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CaptureStats {
+    pub languages: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub min: usize,
+    /// Language using the fewest captures, the first by name on ties.
+    pub min_language: String,
+    pub max: usize,
+    /// Language using the most captures, the last by name on ties.
+    pub max_language: String,
+}
+
+impl CaptureStats {
+    /// Every statistic as a field name and its rendered value, in declaration order.
+    pub fn fields(&self) -> [(&'static str, String); 7] {
+        [
+            ("languages", self.languages.to_string()),
+            ("mean", format!("{:.2}", self.mean)),
+            ("median", self.median.to_string()),
+            ("min", self.min.to_string()),
+            ("min_language", self.min_language.clone()),
+            ("max", self.max.to_string()),
+            ("max_language", self.max_language.clone()),
+        ]
+    }
+}
+
+/// Compute [`CaptureStats`] over the deduplicated captures of each language, if there are any
+/// languages.
+pub fn capture_stats(captures_by_language: &HashMap<String, Vec<String>>) -> Option<CaptureStats> {
+    let capture_counts = distinct_capture_counts(captures_by_language);
+    let mut languages: Vec<(&String, &usize)> = capture_counts.iter().collect();
+    languages.sort_unstable_by(|a, b| a.1.cmp(b.1).then_with(|| a.0.cmp(b.0)));
+
+    let ((min_language, min), (max_language, max)) = (languages.first()?, languages.last()?);
+    let counts: Vec<usize> = languages.iter().map(|(_, count)| **count).collect();
+    #[allow(clippy::cast_precision_loss)]
+    let mean = counts.iter().sum::<usize>() as f64 / counts.len() as f64;
+    #[allow(clippy::cast_precision_loss)]
+    let median = if counts.len().is_multiple_of(2) {
+        (counts[counts.len() / 2 - 1] + counts[counts.len() / 2]) as f64 / 2.0
+    } else {
+        counts[counts.len() / 2] as f64
+    };
+
+    Some(CaptureStats {
+        languages: counts.len(),
+        mean,
+        median,
+        min: **min,
+        min_language: (*min_language).clone(),
+        max: **max,
+        max_language: (*max_language).clone(),
+    })
+}
+
+/// Overlap between the deduplicated capture sets of two languages.
+pub struct CaptureSimilarity {
+    /// Jaccard similarity: the number of shared captures over the number of captures in either set.
+    pub score: f64,
+    pub shared: Vec<String>,
+    pub only_first: Vec<String>,
+    pub only_second: Vec<String>,
+}
+
+/// Compare the captures of two languages. Two languages without any captures are identical.
+pub fn capture_similarity(first: &[String], second: &[String]) -> CaptureSimilarity {
+    let first: HashSet<&String> = first.iter().collect();
+    let second: HashSet<&String> = second.iter().collect();
+
+    let sorted = |captures: Vec<&&String>| {
+        let mut captures: Vec<String> = captures.into_iter().map(|c| (*c).clone()).collect();
+        captures.sort_unstable();
+        captures
+    };
+    let shared = sorted(first.intersection(&second).collect());
+    let union = first.union(&second).count();
+
+    #[allow(clippy::cast_precision_loss)]
+    let score = if union == 0 {
+        1.0
+    } else {
+        shared.len() as f64 / union as f64
+    };
+
+    CaptureSimilarity {
+        score,
+        shared,
+        only_first: sorted(first.difference(&second).collect()),
+        only_second: sorted(second.difference(&first).collect()),
+    }
+}
+
+/// Number of languages and their mean number of distinct captures for each major manifest version,
+/// sorted by major version. Extensions whose version isn't valid semver are left out.
+pub fn capture_counts_by_major_version(
+    extensions: &[Extension],
+    captures_by_language: &HashMap<String, Vec<String>>,
+) -> Vec<(u64, usize, f64)> {
+    let capture_counts = distinct_capture_counts(captures_by_language);
+    let mut counts_by_major: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+
+    for extension in extensions {
+        let Some(capture_count) = capture_counts.get(&extension.id) else {
+            continue;
+        };
+        match semver::Version::parse(extension.metadata.version()) {
+            Ok(version) => counts_by_major
+                .entry(version.major)
+                .or_default()
+                .push(*capture_count),
+            Err(error) => debug!(
+                "skipping '{}' with version '{}': {error}",
+                extension.id,
+                extension.metadata.version()
+            ),
+        }
+    }
+
+    counts_by_major
+        .into_iter()
+        .map(|(major, counts)| {
+            #[allow(clippy::cast_precision_loss)]
+            let mean = counts.iter().sum::<usize>() as f64 / counts.len() as f64;
+            (major, counts.len(), mean)
+        })
+        .collect()
+}
+
+/// Count the number of extensions referencing each grammar, keyed by the grammar repository for
+/// TOML and YAML manifests and by the raw grammar value for JSON manifests.
+pub fn grammar_usage(extensions: &[Extension]) -> HashMap<String, usize> {
+    let mut grammar_counts: HashMap<String, usize> = HashMap::new();
+
+    for extension in extensions {
+        let grammars: HashSet<&String> = match &extension.metadata {
+            ExtensionMetadata::TomlManifest(manifest)
+            | ExtensionMetadata::YamlManifest(manifest) => manifest
+                .grammars
+                .iter()
+                .flat_map(|grammars| grammars.values().map(|grammar| &grammar.repository))
+                .collect(),
+            ExtensionMetadata::JsonManifest(manifest) => manifest
+                .grammars
+                .iter()
+                .flat_map(|grammars| grammars.values())
+                .collect(),
+        };
+
+        for grammar in grammars {
+            *grammar_counts.entry(grammar.clone()).or_default() += 1;
+        }
+    }
+
+    grammar_counts
+}
+
+/// How a grammar reference in a manifest is pinned to a revision.
+pub enum GrammarPin {
+    Commit(String),
+    Rev(String),
+    Floating,
+}
+
+impl fmt::Display for GrammarPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrammarPin::Commit(commit) => write!(f, "commit {commit}"),
+            GrammarPin::Rev(rev) => write!(f, "rev {rev}"),
+            GrammarPin::Floating => write!(f, "floating"),
+        }
+    }
+}
+
+/// Pin of every grammar declared by TOML and YAML manifests, as the extension id, grammar name, and
+/// pin, sorted by id and grammar name. JSON manifests can't pin grammars, so they're left out.
+pub fn grammar_pins(extensions: &[Extension]) -> Vec<(String, String, GrammarPin)> {
+    let mut pins: Vec<(String, String, GrammarPin)> = extensions
+        .iter()
+        .flat_map(|extension| match &extension.metadata {
+            ExtensionMetadata::TomlManifest(manifest)
+            | ExtensionMetadata::YamlManifest(manifest) => manifest
+                .grammars
+                .iter()
+                .flatten()
+                .map(|(name, grammar)| {
+                    let pin = match (&grammar.commit, &grammar.rev) {
+                        (Some(commit), _) => GrammarPin::Commit(commit.clone()),
+                        (None, Some(rev)) => GrammarPin::Rev(rev.clone()),
+                        (None, None) => GrammarPin::Floating,
+                    };
+                    (extension.id.clone(), name.clone(), pin)
+                })
+                .collect::<Vec<_>>(),
+            ExtensionMetadata::JsonManifest(_) => Vec::new(),
+        })
+        .collect();
+
+    pins.sort_unstable_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+    pins
+}
+
+/// Count the number of extensions declaring each language server id.
+pub fn language_server_usage(extensions: &[Extension]) -> HashMap<String, usize> {
+    let mut language_server_counts: HashMap<String, usize> = HashMap::new();
+
+    for extension in extensions {
+        if let ExtensionMetadata::TomlManifest(manifest)
+        | ExtensionMetadata::YamlManifest(manifest) = &extension.metadata
+        {
+            for language_server in manifest.language_servers.iter().flat_map(HashMap::keys) {
+                *language_server_counts
+                    .entry(language_server.clone())
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    language_server_counts
+}
+
+/// Sorted, deduplicated languages served by each language server id across all extensions.
+pub fn language_server_languages(extensions: &[Extension]) -> HashMap<String, Vec<String>> {
+    let mut languages_by_server: HashMap<String, Vec<String>> = HashMap::new();
+
+    for extension in extensions {
+        if let ExtensionMetadata::TomlManifest(manifest)
+        | ExtensionMetadata::YamlManifest(manifest) = &extension.metadata
+        {
+            for (language_server, entry) in manifest.language_servers.iter().flatten() {
+                let languages = languages_by_server
+                    .entry(language_server.clone())
+                    .or_default();
+                languages.extend(entry.language.iter().cloned());
+                languages.extend(entry.languages.iter().flatten().cloned());
+            }
+        }
+    }
+
+    for languages in languages_by_server.values_mut() {
+        languages.sort_unstable();
+        languages.dedup();
+    }
+
+    languages_by_server
+}
+
+/// Count the total usage of each capture namespace (the segment before the first `.`, e.g.
+/// `function` for `function.method`) across all languages.
+pub fn capture_namespace_usage(
+    captures_by_language: &HashMap<String, Vec<String>>,
+) -> HashMap<String, usize> {
+    let mut namespace_counts: HashMap<String, usize> = HashMap::new();
+
+    for capture in captures_by_language.values().flatten() {
+        let namespace = capture.split('.').next().unwrap_or(capture);
+        *namespace_counts.entry(namespace.to_string()).or_default() += 1;
+    }
+
+    namespace_counts
+}
+
+/// Whether a theme color is a valid hex color (`#RGB`, `#RGBA`, `#RRGGBB`, or `#RRGGBBAA`) or a
+/// recognized keyword.
+pub fn is_valid_color(color: &str) -> bool {
+    if color == "transparent" {
+        return true;
+    }
+
+    color.strip_prefix('#').is_some_and(|hex| {
+        matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+/// Syntax style colors that aren't valid, as `(extension id, theme name, capture, color)`.
+pub fn invalid_theme_colors(extensions: &[Extension]) -> Vec<(String, String, String, String)> {
+    let mut invalid_colors: Vec<(String, String, String, String)> = Vec::new();
+
+    for extension in extensions {
+        if let ExtensionType::Theme(theme_extension) = &extension.r#type {
+            for theme in theme_extension.themes.iter().flatten() {
+                for (theme_name, capture, color) in theme.syntax_colors() {
+                    if !is_valid_color(&color) {
+                        invalid_colors.push((extension.id.clone(), theme_name, capture, color));
+                    }
+                }
+            }
+        }
+    }
+
+    invalid_colors.sort_unstable();
+    invalid_colors
+}
+
+/// Count the individual light and dark themes across all theme extensions, as `(light, dark)`.
+pub fn theme_appearance_counts(extensions: &[Extension]) -> (usize, usize) {
+    let mut light = 0;
+    let mut dark = 0;
+
+    for extension in extensions {
+        if let ExtensionType::Theme(theme_extension) = &extension.r#type {
+            for appearance in theme_extension
+                .themes
+                .iter()
+                .flatten()
+                .flat_map(Theme::appearances)
+            {
+                match appearance {
+                    Appearance::Light => light += 1,
+                    Appearance::Dark => dark += 1,
+                }
+            }
+        }
+    }
+
+    (light, dark)
+}
+
+/// Number of syntax captures without any style in each theme that has at least one, as
+/// `(extension id, theme name, count)`, sorted.
+pub fn empty_theme_captures(extensions: &[Extension]) -> Vec<(String, String, usize)> {
+    let mut empty_captures: HashMap<(String, String), usize> = HashMap::new();
+
+    for extension in extensions {
+        if let ExtensionType::Theme(theme_extension) = &extension.r#type {
+            for theme in theme_extension.themes.iter().flatten() {
+                for (theme_name, _) in theme.empty_syntax_captures() {
+                    *empty_captures
+                        .entry((extension.id.clone(), theme_name))
+                        .or_default() += 1;
+                }
+            }
+        }
+    }
+
+    let mut empty_captures: Vec<(String, String, usize)> = empty_captures
+        .into_iter()
+        .map(|((id, theme_name), count)| (id, theme_name, count))
+        .collect();
+    empty_captures.sort_unstable();
+    empty_captures
+}
+
+/// Languages whose `first_line_pattern` isn't a valid regular expression, as
+/// `(extension id, language name, error)`, sorted.
+pub fn invalid_first_line_patterns(extensions: &[Extension]) -> Vec<(String, String, String)> {
+    let mut invalid_patterns: Vec<(String, String, String)> = Vec::new();
+
+    for extension in extensions {
+        if let ExtensionType::Language(language_extension) = &extension.r#type {
+            for language in &language_extension.languages {
+                let Some(pattern) = &language.config.first_line_pattern else {
+                    continue;
+                };
+
+                if let Err(error) = Regex::new(pattern) {
+                    invalid_patterns.push((
+                        extension.id.clone(),
+                        language.config.name.clone(),
+                        error.to_string(),
+                    ));
+                }
+            }
+        }
+    }
+
+    invalid_patterns.sort_unstable();
+    invalid_patterns
+}
+
+/// Path suffixes claimed by more than one language, with the sorted `extension id (language name)`
+/// of each claimant, sorted by suffix.
+pub fn path_suffix_conflicts(extensions: &[Extension]) -> Vec<(String, Vec<String>)> {
+    let mut claimants_by_suffix: HashMap<String, Vec<String>> = HashMap::new();
+
+    for extension in extensions {
+        if let ExtensionType::Language(language_extension) = &extension.r#type {
+            for language in &language_extension.languages {
+                for suffix in language.config.path_suffixes.iter().flatten() {
+                    claimants_by_suffix
+                        .entry(suffix.clone())
+                        .or_default()
+                        .push(format!("{} ({})", extension.id, language.config.name));
+                }
+            }
+        }
+    }
+
+    let mut conflicts: Vec<(String, Vec<String>)> = claimants_by_suffix
+        .into_iter()
+        .filter_map(|(suffix, mut claimants)| {
+            claimants.sort_unstable();
+            claimants.dedup();
+            (claimants.len() > 1).then_some((suffix, claimants))
+        })
+        .collect();
+    conflicts.sort_unstable();
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(captures: &[&str]) -> Vec<String> {
+        captures.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn language_without_captures_scores_zero() {
+        let captures_by_language = HashMap::from([
+            ("Empty".to_string(), Vec::new()),
+            ("Rust".to_string(), owned(&["comment", "string"])),
+        ]);
+        let supported_captures_by_theme = HashMap::from([
+            ("dark".to_string(), owned(&["comment", "string"])),
+            ("light".to_string(), owned(&["comment"])),
+        ]);
+
+        let scores =
+            language_theme_support_scores(&captures_by_language, &supported_captures_by_theme);
+
+        assert_eq!(scores["Empty"], 0);
+        // Depth: (2 + 1) themes over 2 captures, breadth: 2 themes.
+        assert_eq!(scores["Rust"], 7 * 3 / 2 + 3 * 2);
+    }
+
+    #[test]
+    fn extract_capture_names_handles_malformed_queries() {
+        let (mut ts_parser, ts_query) = capture_query();
+        let mut cursor = QueryCursor::new();
+
+        let error = extract_capture_names(
+            "(comment) @comment\n(string @string",
+            &mut ts_parser,
+            &ts_query,
+            &mut cursor,
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "query file has a syntax error on line 2");
+
+        let captures = extract_capture_names(
+            "(comment) @comment\n(string) @string",
+            &mut ts_parser,
+            &ts_query,
+            &mut cursor,
+        )
+        .unwrap();
+        assert_eq!(captures, ["comment", "string"]);
+
+        // Without a language the parser can't produce a tree at all.
+        let mut parser_without_language = tree_sitter::Parser::new();
+        assert!(extract_capture_names(
+            "(comment) @comment",
+            &mut parser_without_language,
+            &ts_query,
+            &mut cursor,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn private_captures_are_only_included_on_request() {
+        let (scan_dump, _) = crate::scan::local_extensions(
+            &std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/extensions"),
+        )
+        .unwrap();
+
+        let captures = captures_by_language(&scan_dump.extensions, &QueryKind::Highlights, false);
+        assert!(captures["test-language"].contains(&"comment".to_string()));
+        assert!(!captures["test-language"].contains(&"_private".to_string()));
+
+        let captures = captures_by_language(&scan_dump.extensions, &QueryKind::Highlights, true);
+        assert!(captures["test-language"].contains(&"_private".to_string()));
+    }
+
+    #[test]
+    fn capture_stats_count_distinct_captures() {
+        assert_eq!(capture_stats(&HashMap::new()), None);
+
+        let captures_by_language = HashMap::from([
+            ("C".to_string(), owned(&["comment"])),
+            ("Go".to_string(), owned(&["comment", "string", "string"])),
+            (
+                "Rust".to_string(),
+                owned(&["comment", "string", "type", "variable"]),
+            ),
+            ("Zig".to_string(), owned(&["string"])),
+        ]);
+
+        assert_eq!(
+            capture_stats(&captures_by_language),
+            Some(CaptureStats {
+                languages: 4,
+                mean: 2.0,
+                median: 1.5,
+                min: 1,
+                min_language: "C".to_string(),
+                max: 4,
+                max_language: "Rust".to_string(),
+            })
+        );
+    }
+}
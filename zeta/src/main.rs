@@ -1,58 +1,150 @@
 #![warn(clippy::pedantic, clippy::all)]
 
-use anyhow::Result;
-use clap::{arg, Parser, Subcommand, ValueEnum};
-use log::debug;
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use globset::{GlobBuilder, GlobSetBuilder};
+use log::{debug, warn};
+use regex::Regex;
 use std::{
     collections::{HashMap, HashSet},
     fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 
-use streaming_iterator::StreamingIterator;
-use tree_sitter::QueryCursor;
-
 use zeta::{
-    scan,
-    types::{Extension, ExtensionMetadata, ExtensionType, Theme},
+    analysis::{self, CaptureTables, GrammarPin, QueryKind},
+    scan::{self, ScanProblem},
+    types::{
+        Appearance, Extension, ExtensionMetadata, ExtensionType, ScanDump, Theme, TomlManifest,
+    },
 };
 
+// Each flag is an independent switch, so there's no state machine hiding in these bools.
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Parser)]
 #[command(version, about, arg_required_else_help(true))]
 struct Cli {
     #[command(subcommand)]
     pub command: Commands,
 
+    /// Rescan every extension from scratch, ignoring the cached scan.
     #[arg(long)]
     pub refresh: bool,
+
+    /// Fetch the extensions repository and rescan only if its HEAD moved since the cached scan.
+    #[arg(long)]
+    pub refresh_if_changed: bool,
+
+    /// Rescan if the cached scan is older than this duration (e.g. `24h`, `7d`); `0` always rescans.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub max_age: Option<Duration>,
+
+    /// Scan every extension from scratch without reading or writing the scan cache. Implies
+    /// `--refresh`.
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// Git URL of the repository tracking extensions as submodules.
+    #[arg(long, default_value = scan::DEFAULT_EXTENSIONS_REPOSITORY)]
+    pub extensions_repo: String,
+
+    /// Clone the extensions repository with a depth of 1. Submodules are always fetched in full.
+    #[arg(long)]
+    pub shallow: bool,
+
+    /// Abort git network operations when the remote doesn't respond for this many seconds; `0`
+    /// disables the timeout. A submodule that times out is reported as a scan error instead of
+    /// stalling the scan.
+    #[arg(long, default_value_t = scan::DEFAULT_GIT_TIMEOUT.as_secs())]
+    pub git_timeout: u64,
+
+    /// Scan extensions from an already checked-out extensions directory instead of cloning the
+    /// extensions repository. Local scans are never cached.
+    #[arg(long)]
+    pub local_dir: Option<PathBuf>,
+
+    /// Output format for analysis and `find` results.
+    #[arg(long, global = true, default_value = "text")]
+    pub format: OutputFormat,
+
+    /// Leave builtin extensions out of analysis results.
+    #[arg(long, global = true)]
+    pub exclude_builtin: bool,
+
+    /// Log warnings about anything skipped while scanning, such as language directories without a
+    /// `config.toml`.
+    #[arg(long, global = true)]
+    pub verbose: bool,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
     Show {
         id: String,
+
+        /// Print a compact summary instead of the full JSON dump.
+        #[arg(long)]
+        summary: bool,
+
+        /// Print only these comma-separated fields of the JSON dump, as dotted paths (e.g.
+        /// `id,metadata.name`).
+        #[arg(long, value_delimiter = ',', conflicts_with = "summary")]
+        fields: Vec<String>,
+    },
+    /// Print the stored query source of each language of a language extension.
+    ShowQuery {
+        id: String,
+
+        #[arg(long, default_value = "highlights")]
+        kind: QueryKind,
     },
     /// Find extensions matching certain criteria.
     Find {
-        #[arg(long)]
-        manifest: Option<BasicManifestType>,
-
-        #[arg(long)]
-        r#type: Option<BasicExtensionType>,
+        #[command(flatten)]
+        filters: Box<FindFilters>,
 
         #[arg(long)]
-        git_provider: Option<String>,
+        count: bool,
 
+        /// Print the matching extensions (or the count) as JSON; shorthand for `--format json`.
         #[arg(long)]
-        theme_schema: Option<BasicThemeSchema>,
-
+        json: bool,
+    },
+    /// List the ids of all extensions, one per line.
+    List {
         #[arg(long)]
-        builtin: Option<bool>,
+        r#type: Option<BasicExtensionType>,
 
         #[arg(long)]
         count: bool,
     },
+    /// Fuzzy search extensions by id and name, best match first.
+    Search {
+        query: String,
+
+        #[arg(short, long, default_value = "10")]
+        limit: usize,
+    },
+    /// Print an overview of the whole extension ecosystem.
+    Stats,
+    /// Write the capture tables used by analysis queries (highlight captures by language, captures
+    /// supported by each theme, and all used captures) to a JSON file.
+    Export { out: PathBuf },
+    /// Rescan extensions and report every extension with a problem, grouped by problem.
+    Doctor,
+    /// Compare two scan dumps, reporting added, removed, and updated extensions.
+    Diff { old: PathBuf, new: PathBuf },
     /// Analyze extensions with various queries, mostly related to captures.
     Analyze {
+        /// Print results as JSON; shorthand for `--format json`.
+        #[arg(long)]
+        json: bool,
+
+        /// Include private (`_`-prefixed) captures used by languages.
+        #[arg(long)]
+        include_private: bool,
+
         #[command(subcommand)]
         query: AnalysisQuery,
     },
@@ -66,10 +158,28 @@ pub enum SortOrder {
     Descending,
 }
 
+#[derive(Clone, ValueEnum)]
+pub enum SortKey {
+    Count,
+    Name,
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Csv,
+    Json,
+    /// Aligned columns with a header.
+    Table,
+    /// One compact JSON value per line.
+    Ndjson,
+}
+
 #[derive(Clone, ValueEnum)]
 pub enum BasicManifestType {
     Json,
     Toml,
+    Yaml,
 }
 
 #[derive(Clone, ValueEnum)]
@@ -80,424 +190,1824 @@ pub enum BasicExtensionType {
     ContextServer,
 }
 
+impl BasicExtensionType {
+    fn matches(&self, extension_type: &ExtensionType) -> bool {
+        match self {
+            BasicExtensionType::Theme => matches!(extension_type, ExtensionType::Theme(_)),
+            BasicExtensionType::Language => matches!(extension_type, ExtensionType::Language(_)),
+            BasicExtensionType::SlashCommand => {
+                matches!(extension_type, ExtensionType::SlashCommand)
+            }
+            BasicExtensionType::ContextServer => {
+                matches!(extension_type, ExtensionType::ContextServer)
+            }
+        }
+    }
+}
+
 #[derive(Clone, ValueEnum)]
 pub enum BasicThemeSchema {
     V1,
     V2,
+    /// Only matches anything when built with the v0.3.0 schema.
+    V3,
     Other,
 }
 
-#[derive(Subcommand)]
-pub enum AnalysisQuery {
-    /// Query the most (order: desc) or least (order: asc) used captures in language extensions.
-    CapturesByUsage {
-        order: SortOrder,
-
-        #[arg(short, long, default_value = "10")]
-        limit: usize,
-    },
-    /// Query the most (order: desc) or least (order: asc) supported captures in theme extensions.
-    CapturesByThemeSupport {
-        order: SortOrder,
+/// Options shared by queries producing a ranked list.
+#[derive(Args)]
+pub struct RankingOptions {
+    order: SortOrder,
 
-        #[arg(short, long, default_value = "10")]
-        limit: usize,
-    },
+    #[arg(short, long, default_value = "10")]
+    limit: usize,
 
-    /// Query the themes supporting a specific capture.
-    ThemesSupportingCapture {
-        capture: String,
+    /// Exclude entries with a count below this threshold (applied before `--limit`).
+    #[arg(long, default_value = "0")]
+    min_count: usize,
 
-        #[arg(long)]
-        count: bool,
-    },
-    /// Query the languages using a specific capture.
-    LanguagesUsingCapture {
-        capture: String,
+    /// Print the total number of distinct entries (before `--min-count` and `--limit`) after the
+    /// ranking.
+    #[arg(long)]
+    with_total: bool,
 
-        #[arg(long)]
-        count: bool,
-    },
+    /// Sort the ranking by count, or by name. Sorting by name keeps the entries with the highest
+    /// counts (up to `--limit`) and then orders them by name in the direction of `<ORDER>`.
+    #[arg(long, default_value = "count")]
+    sort: SortKey,
+}
 
-    /// Roughly score and rank languages by the depth (average number of themes supporting each capture used in a language) and breadth (number of themes supporting at least one capture) of theme support.
-    /// The score is calculated as 7 * depth / number of captures + 3 * breadth.
-    /// The best languages will have a high score (order: desc) and the worst languages will have a low score (order: asc).
-    LanguagesByThemeSupport {
-        order: SortOrder,
+/// Criteria of the Find command. An extension has to match every criterion that's set.
+#[derive(Args, Default)]
+pub struct FindFilters {
+    #[arg(long)]
+    manifest: Option<BasicManifestType>,
 
-        #[arg(short, long, default_value = "10")]
-        limit: usize,
-    },
-    /// Query the themes supporting the most (order: desc) or least (order: asc) *USED* captures. Captures are considered used if they are used in any language extension.
-    ThemesByCaptureSupport {
-        order: SortOrder,
+    /// Match extensions whose id matches this regular expression.
+    #[arg(long, value_parser = Regex::new)]
+    id_regex: Option<Regex>,
 
-        #[arg(short, long, default_value = "10")]
-        limit: usize,
-    },
-}
+    #[arg(long)]
+    r#type: Option<BasicExtensionType>,
 
-fn main() -> Result<()> {
-    env_logger::init();
-    debug!("logger initialized");
+    #[arg(long)]
+    git_provider: Option<String>,
 
-    let args: Cli = Cli::parse();
+    /// Match extensions whose submodule Git URL contains this string.
+    #[arg(long)]
+    git_url: Option<String>,
 
-    let cache_dir = user_dirs::cache_dir()?.join("ts-ecosystem-zeta");
-    let extensions_scan_cache = cache_dir.join("extensions-scan-dump.json");
-
-    let cache_result = || -> Result<Vec<Extension>> {
-        Ok(
-            fs::read_to_string(&extensions_scan_cache).and_then(|contents| {
-                serde_json_lenient::from_str::<Vec<Extension>>(&contents)
-                    .map_err(std::convert::Into::into)
-            })?,
-        )
-    };
+    #[arg(long)]
+    theme_schema: Option<BasicThemeSchema>,
 
-    let (extensions, cache_hit) = if args.refresh {
-        (scan::extensions(&cache_dir)?, false)
-    } else {
-        match cache_result() {
-            Ok(extensions) => (extensions, true),
-            Err(_) => (scan::extensions(&cache_dir)?, false),
-        }
-    };
+    #[arg(long)]
+    builtin: Option<bool>,
 
-    if !cache_hit {
-        fs::write(
-            &extensions_scan_cache,
-            serde_json_lenient::to_string(&extensions)?,
-        )?;
-    }
+    /// Only match builtin extensions; shorthand for `--builtin true`.
+    #[arg(long, conflicts_with_all = ["builtin", "external_only"])]
+    builtin_only: bool,
 
-    match args.command {
-        Commands::Analyze { query } => handle_query(query, extensions),
-        Commands::Find {
-            manifest,
-            r#type,
-            git_provider,
-            theme_schema,
-            builtin,
-            count,
-        } => {
-            let matching: Vec<Extension> = extensions
-                .into_iter()
-                .filter(|extension| {
-                    match &manifest {
-                        Some(BasicManifestType::Json) => {
-                            if let ExtensionMetadata::TomlManifest(_) = extension.metadata {
-                                return false;
-                            };
-                        }
-                        Some(BasicManifestType::Toml) => {
-                            if let ExtensionMetadata::JsonManifest(_) = extension.metadata {
-                                return false;
-                            };
-                        }
-                        None => {}
-                    };
+    /// Only match external (non-builtin) extensions; shorthand for `--builtin false`.
+    #[arg(long, conflicts_with = "builtin")]
+    external_only: bool,
 
-                    // Check if the extension has the same type as the provided type.
-                    match &r#type {
-                        Some(extension_type) => match extension_type {
-                            BasicExtensionType::Theme => {
-                                if !matches!(extension.r#type, ExtensionType::Theme(_)) {
-                                    return false;
-                                }
-                            }
-                            BasicExtensionType::Language => {
-                                if !matches!(extension.r#type, ExtensionType::Language(_)) {
-                                    return false;
-                                }
-                            }
-                            BasicExtensionType::SlashCommand => {
-                                if !matches!(extension.r#type, ExtensionType::SlashCommand) {
-                                    return false;
-                                }
-                            }
-                            BasicExtensionType::ContextServer => {
-                                if !matches!(extension.r#type, ExtensionType::ContextServer) {
-                                    return false;
-                                }
-                            }
-                        },
-                        None => {}
-                    };
+    /// Match theme extensions containing at least one theme with this appearance.
+    #[arg(long)]
+    appearance: Option<Appearance>,
 
-                    // Check if the extension has the same git provider as the provided git provider.
-                    if let Some(provider) = &git_provider {
-                        if extension.git_provider.as_deref() != Some(provider) {
-                            return false;
-                        }
-                    }
+    /// Match extensions with an author containing this name (case-insensitive); can be repeated.
+    #[arg(long)]
+    author: Vec<String>,
 
-                    // Check if the extension has the same theme schema as the provided theme schema.
-                    // This is a bit more complex because the theme schema is nested.
-                    if let Some(schema) = &theme_schema {
-                        if let ExtensionType::Theme(theme_extension) = &extension.r#type {
-                            if theme_extension.themes.iter().any(|theme| match theme {
-                                Some(Theme::V1(Some(_))) => matches!(schema, BasicThemeSchema::V1),
-                                Some(Theme::V2(Some(_))) => matches!(schema, BasicThemeSchema::V2),
-                                _ => false,
-                            }) {
-                                return false;
-                            }
-                        } else {
-                            return false;
-                        }
-                    }
+    /// Match extensions whose manifest repository URL contains this string (case-insensitive).
+    #[arg(long)]
+    repository: Option<String>,
 
-                    if let Some(builtin) = builtin {
-                        if extension.builtin != builtin {
-                            return false;
-                        }
-                    }
+    /// Match language extensions shipping this query file in at least one language; can be
+    /// repeated to require several query files.
+    #[arg(long)]
+    has_query: Vec<QueryKind>,
 
-                    true
-                })
-                .collect();
+    /// Match extensions declaring this grammar in their manifest or using it for a language.
+    #[arg(long)]
+    grammar: Option<String>,
 
-            println!("{}", count_or_list(matching, count));
-        }
-        Commands::Show { id } => {
-            let extension = extensions
-                .into_iter()
-                .find(|extension| extension.id == id)
-                .expect("extension not found");
+    /// Match language extensions defining a language with this display name (case-insensitive).
+    #[arg(long)]
+    language_name: Option<String>,
 
-            println!("{}", serde_json_lenient::to_string_pretty(&extension)?);
-        }
-    }
+    /// Match extensions whose TOML or YAML manifest declares at least one language server.
+    #[arg(long)]
+    has_language_server: bool,
 
-    Ok(())
-}
+    /// Match extensions whose TOML or YAML manifest declares this language server id.
+    #[arg(long)]
+    language_server: Option<String>,
 
-fn count_or_list<T: ToString>(items: Vec<T>, count: bool) -> String {
-    if count {
-        items.len().to_string()
-    } else {
-        items
-            .into_iter()
-            .map(|item| item.to_string())
-            .collect::<Vec<String>>()
-            .join("\n")
-    }
-}
+    /// Match language extensions with at least one language using this tab size. Combined with
+    /// `--hard-tabs`, the same language has to match both.
+    #[arg(long)]
+    tab_size: Option<usize>,
 
-fn sort_truncate_display_hashmap(map: &HashMap<String, usize>, order: &SortOrder, limit: usize) {
-    let mut sorted_map: Vec<(&String, &usize)> = map.iter().collect();
+    /// Match language extensions with at least one language setting `hard_tabs` to this value.
+    #[arg(long)]
+    hard_tabs: Option<bool>,
 
-    match order {
-        SortOrder::Asc | SortOrder::Ascending => {
-            sorted_map.sort_unstable_by(|a, b| a.1.cmp(b.1));
-        }
-        SortOrder::Desc | SortOrder::Descending => {
-            sorted_map.sort_unstable_by(|a, b| b.1.cmp(a.1));
-        }
-    }
+    /// Match theme extensions with at least this many valid theme files.
+    #[arg(long)]
+    min_themes: Option<usize>,
 
-    if limit != 0 {
-        sorted_map.truncate(limit);
-    }
+    /// Match theme extensions with at most this many valid theme files.
+    #[arg(long)]
+    max_themes: Option<usize>,
 
-    for (key, value) in sorted_map {
-        println!("{key}: {value}");
-    }
+    /// Match extensions with a TOML manifest declaring this schema version.
+    #[arg(long)]
+    schema_version: Option<usize>,
 }
 
-fn extract_capture_names(
-    source_code: &str,
-    ts_parser: &mut tree_sitter::Parser,
-    ts_query: &tree_sitter::Query,
-) -> Option<Vec<String>> {
-    let tree = ts_parser.parse(source_code, None)?;
-    let mut cursor = QueryCursor::new();
-    let text_bytes = source_code.as_bytes();
-    let mut captures = cursor.captures(ts_query, tree.root_node(), text_bytes);
+impl FindFilters {
+    #[allow(clippy::too_many_lines)]
+    fn matches(&self, extension: &Extension) -> bool {
+        // Check if the extension's id matches the provided regular expression.
+        if let Some(id_regex) = &self.id_regex {
+            if !id_regex.is_match(&extension.id) {
+                return false;
+            }
+        }
+
+        // Check if the extension has the provided manifest type.
+        if let Some(manifest) = &self.manifest {
+            let matches_manifest = match manifest {
+                BasicManifestType::Json => {
+                    matches!(extension.metadata, ExtensionMetadata::JsonManifest(_))
+                }
+                BasicManifestType::Toml => {
+                    matches!(extension.metadata, ExtensionMetadata::TomlManifest(_))
+                }
+                BasicManifestType::Yaml => {
+                    matches!(extension.metadata, ExtensionMetadata::YamlManifest(_))
+                }
+            };
+            if !matches_manifest {
+                return false;
+            }
+        }
 
-    let mut capture_names: Vec<String> = Vec::new();
-    while let Some((c, _)) = captures.next() {
-        for capture in c.captures {
-            capture_names.push(capture.node.utf8_text(text_bytes).unwrap().to_string());
+        // Check if the extension has the same type as the provided type.
+        if let Some(extension_type) = &self.r#type {
+            if !extension_type.matches(&extension.r#type) {
+                return false;
+            }
         }
-    }
 
-    Some(capture_names)
-}
+        // Check if the extension has the same git provider as the provided git provider.
+        if let Some(provider) = &self.git_provider {
+            if extension.git_provider.as_deref() != Some(provider) {
+                return false;
+            }
+        }
 
-fn handle_query(query: AnalysisQuery, extensions: Vec<Extension>) {
-    let mut supported_captures_by_theme: HashMap<String, Vec<String>> = HashMap::new();
-    let mut captures_by_language: HashMap<String, Vec<String>> = HashMap::new();
+        // Check if the extension's Git URL contains the provided Git URL.
+        if let Some(git_url) = &self.git_url {
+            if !extension
+                .git_url
+                .as_ref()
+                .is_some_and(|url| url.contains(git_url.as_str()))
+            {
+                return false;
+            }
+        }
 
-    let mut ts_parser = tree_sitter::Parser::new();
-    ts_parser
-        .set_language(&tree_sitter_query::LANGUAGE.into())
-        .expect("should load tree-sitter-query grammar");
+        // Check if the extension has the same theme schema as the provided theme schema.
+        // This is a bit more complex because the theme schema is nested.
+        if let Some(schema) = &self.theme_schema {
+            if let ExtensionType::Theme(theme_extension) = &extension.r#type {
+                if !theme_extension.themes.iter().any(|theme| match theme {
+                    Some(Theme::V1(Some(_))) => matches!(schema, BasicThemeSchema::V1),
+                    Some(Theme::V2(Some(_))) => matches!(schema, BasicThemeSchema::V2),
+                    #[cfg(themes_v3)]
+                    Some(Theme::V3(Some(_))) => matches!(schema, BasicThemeSchema::V3),
+                    _ => matches!(schema, BasicThemeSchema::Other),
+                }) {
+                    return false;
+                }
+            } else {
+                return false;
+            }
+        }
 
-    let ts_query = tree_sitter::Query::new(
-        &tree_sitter_query::LANGUAGE.into(),
-        "(capture (identifier) @name)",
-    )
-    .expect("tree-sitter-query capture query should build");
+        let builtin = match (self.builtin_only, self.external_only) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => self.builtin,
+        };
+        if let Some(builtin) = builtin {
+            if extension.builtin != builtin {
+                return false;
+            }
+        }
 
-    for extension in extensions {
-        match extension.r#type {
-            ExtensionType::Theme(theme_extension) => {
-                let mut syntax_captures: Vec<String> = theme_extension
+        // Check if the extension has a theme with the provided appearance.
+        if let Some(appearance) = self.appearance {
+            if let ExtensionType::Theme(theme_extension) = &extension.r#type {
+                if !theme_extension
                     .themes
                     .iter()
-                    .flat_map(|theme| match theme {
-                        Some(Theme::V1(Some(theme))) => theme
-                            .themes
-                            .iter()
-                            .flat_map(|theme| theme.style.syntax.keys())
-                            .collect::<Vec<&String>>(),
-                        Some(Theme::V2(Some(theme))) => theme
-                            .themes
-                            .iter()
-                            .flat_map(|theme| theme.style.syntax.keys())
-                            .collect::<Vec<&String>>(),
-                        _ => Vec::new(),
-                    })
-                    .cloned()
-                    .collect();
+                    .flatten()
+                    .any(|theme| theme.appearances().contains(&appearance))
+                {
+                    return false;
+                }
+            } else {
+                return false;
+            }
+        }
 
-                syntax_captures.sort_unstable();
-                syntax_captures.dedup();
+        // Check if any of the extension's authors match any of the provided authors.
+        if !self.author.is_empty()
+            && !extension.metadata.authors().iter().any(|extension_author| {
+                let extension_author = extension_author.to_lowercase();
+                self.author
+                    .iter()
+                    .any(|name| extension_author.contains(&name.to_lowercase()))
+            })
+        {
+            return false;
+        }
 
-                supported_captures_by_theme.insert(extension.id, syntax_captures);
+        // Check if the extension's manifest repository contains the provided repository.
+        if let Some(repository) = &self.repository {
+            if !extension
+                .metadata
+                .repository()
+                .to_lowercase()
+                .contains(&repository.to_lowercase())
+            {
+                return false;
             }
-            ExtensionType::Language(language_extension) => {
-                let captures: Vec<String> = language_extension
-                    .languages
-                    .iter()
-                    .filter_map(|language| {
-                        if let Some(highlights) = &language.highlights_queries {
-                            extract_capture_names(highlights, &mut ts_parser, &ts_query)
-                        } else {
-                            None
-                        }
-                    })
-                    .flatten()
-                    .filter(|capture| !capture.starts_with('_'))
-                    .collect();
+        }
 
-                captures_by_language.insert(extension.id, captures);
+        // Check if the extension ships all of the provided query files.
+        if !self.has_query.is_empty() {
+            if let ExtensionType::Language(language_extension) = &extension.r#type {
+                if !self.has_query.iter().all(|kind| {
+                    language_extension
+                        .languages
+                        .iter()
+                        .any(|language| kind.source(language).is_some())
+                }) {
+                    return false;
+                }
+            } else {
+                return false;
             }
-            ExtensionType::SlashCommand | ExtensionType::ContextServer => {}
         }
-    }
 
-    match query {
-        AnalysisQuery::CapturesByUsage { order, limit } => {
-            let mut capture_counts: HashMap<String, usize> = HashMap::new();
-            let captures_by_language: Vec<String> = captures_by_language
-                .into_values()
-                .flat_map(|mut values| {
-                    let mut seen = HashSet::new();
-                    values
-                        .drain(..)
-                        .filter(|item| seen.insert(item.clone()))
-                        .collect::<Vec<String>>()
-                })
-                .collect();
+        // Check if the extension declares or uses the provided grammar.
+        if let Some(grammar) = &self.grammar {
+            let uses_grammar = match &extension.r#type {
+                ExtensionType::Language(language_extension) => language_extension
+                    .languages
+                    .iter()
+                    .any(|language| &language.config.grammar == grammar),
+                _ => false,
+            };
+            if !extension.metadata.declares_grammar(grammar) && !uses_grammar {
+                return false;
+            }
+        }
 
-            for capture in captures_by_language {
-                *capture_counts.entry(capture).or_default() += 1;
+        // Check if the extension defines a language with the provided display name.
+        if let Some(language_name) = &self.language_name {
+            let ExtensionType::Language(language_extension) = &extension.r#type else {
+                return false;
+            };
+            if !language_extension
+                .languages
+                .iter()
+                .any(|language| language.config.name.eq_ignore_ascii_case(language_name))
+            {
+                return false;
             }
+        }
 
-            sort_truncate_display_hashmap(&capture_counts, &order, limit);
+        // Check if any of the extension's languages has the provided indentation settings.
+        if self.tab_size.is_some() || self.hard_tabs.is_some() {
+            let ExtensionType::Language(language_extension) = &extension.r#type else {
+                return false;
+            };
+            if !language_extension.languages.iter().any(|language| {
+                self.tab_size
+                    .is_none_or(|tab_size| language.config.tab_size == Some(tab_size))
+                    && self
+                        .hard_tabs
+                        .is_none_or(|hard_tabs| language.config.hard_tabs == Some(hard_tabs))
+            }) {
+                return false;
+            }
         }
-        AnalysisQuery::CapturesByThemeSupport { order, limit } => {
-            let mut capture_counts: HashMap<String, usize> = HashMap::new();
-            for captures in supported_captures_by_theme.values() {
-                for capture in captures {
-                    *capture_counts.entry(capture.clone()).or_default() += 1;
+
+        // Check if the extension's manifest declares language servers. JSON manifests
+        // can't declare language servers, so they never match.
+        if self.has_language_server || self.language_server.is_some() {
+            let (ExtensionMetadata::TomlManifest(manifest)
+            | ExtensionMetadata::YamlManifest(manifest)) = &extension.metadata
+            else {
+                return false;
+            };
+            let Some(language_servers) = &manifest.language_servers else {
+                return false;
+            };
+            if language_servers.is_empty() {
+                return false;
+            }
+            if let Some(language_server) = &self.language_server {
+                if !language_servers.contains_key(language_server) {
+                    return false;
                 }
             }
-
-            sort_truncate_display_hashmap(&capture_counts, &order, limit);
         }
 
-        AnalysisQuery::ThemesSupportingCapture { capture, count } => {
-            let themes_with_support = supported_captures_by_theme
+        // Check if the extension's number of valid themes is within the provided range.
+        if self.min_themes.is_some() || self.max_themes.is_some() {
+            let ExtensionType::Theme(theme_extension) = &extension.r#type else {
+                return false;
+            };
+            let valid_themes = theme_extension
+                .themes
                 .iter()
-                .filter(|(_, supported_captures)| supported_captures.contains(&capture));
-            println!(
-                "{}",
-                if count {
-                    themes_with_support.count().to_string()
-                } else {
-                    themes_with_support
-                        .map(|(theme, _)| theme)
-                        .cloned()
-                        .collect::<Vec<String>>()
-                        .join("\n")
-                }
-            );
+                .flatten()
+                .filter(|theme| theme.is_valid())
+                .count();
+            if self
+                .min_themes
+                .is_some_and(|min_themes| valid_themes < min_themes)
+                || self
+                    .max_themes
+                    .is_some_and(|max_themes| valid_themes > max_themes)
+            {
+                return false;
+            }
         }
-        AnalysisQuery::LanguagesUsingCapture { capture, count } => {
-            let languages_using_capture = captures_by_language
-                .iter()
-                .filter_map(|(language, captures)| {
-                    if captures.contains(&capture) {
-                        Some(language)
-                    } else {
-                        None
-                    }
-                })
-                .collect();
 
-            println!("{}", count_or_list(languages_using_capture, count));
+        // Check if the extension's TOML or YAML manifest has the provided schema version.
+        // JSON manifests have no schema version, so they never match.
+        if let Some(schema_version) = self.schema_version {
+            match &extension.metadata {
+                ExtensionMetadata::TomlManifest(manifest)
+                | ExtensionMetadata::YamlManifest(manifest)
+                    if manifest.schema_version == Some(schema_version) => {}
+                _ => return false,
+            }
         }
 
-        AnalysisQuery::LanguagesByThemeSupport { order, limit } => {
-            let mut language_support_scores: HashMap<String, usize> = HashMap::new();
+        true
+    }
+}
 
-            for (language, captures) in &captures_by_language {
-                // Average number of themes supporting each capture.
-                let capture_support_depth: usize = captures
-                    .iter()
-                    .map(|capture| {
-                        supported_captures_by_theme
-                            .values()
-                            .filter(|captures| captures.contains(capture))
-                            .count()
-                    })
-                    .sum();
+#[derive(Subcommand)]
+pub enum AnalysisQuery {
+    /// Query the most (order: desc) or least (order: asc) used captures in language extensions.
+    CapturesByUsage {
+        #[command(flatten)]
+        ranking: RankingOptions,
 
-                // Count of themes supporting at least one capture.
-                let theme_support_breadth = supported_captures_by_theme
-                    .values()
-                    .filter(|supported_captures| {
-                        supported_captures
-                            .iter()
-                            .any(|capture| captures.contains(capture))
-                    })
-                    .count();
+        #[arg(long, default_value = "highlights")]
+        kind: QueryKind,
 
-                let scaled_capture_support_depth = 7 * capture_support_depth / captures.len();
-                let scaled_theme_support_breadth = 3 * theme_support_breadth;
+        /// Only rank captures starting with this prefix (e.g. `function.`).
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+    /// Query the most (order: desc) or least (order: asc) used captures in the injection queries of language extensions.
+    InjectionCapturesByUsage {
+        #[command(flatten)]
+        ranking: RankingOptions,
+    },
+    /// Query the most (order: desc) or least (order: asc) used captures in the bracket queries of language extensions.
+    BracketCaptures {
+        #[command(flatten)]
+        ranking: RankingOptions,
+    },
+    /// Query the most (order: desc) or least (order: asc) used captures in the outline queries of language extensions.
+    OutlineCaptures {
+        #[command(flatten)]
+        ranking: RankingOptions,
 
-                let support_score = scaled_capture_support_depth + scaled_theme_support_breadth;
+        /// Instead, list the languages whose outline queries use none of the core captures
+        /// (`name`, `item`, `context`).
+        #[arg(long)]
+        missing_core: bool,
+    },
+    /// Query the most (order: desc) or least (order: asc) supported captures in theme extensions.
+    CapturesByThemeSupport {
+        #[command(flatten)]
+        ranking: RankingOptions,
 
-                language_support_scores.insert(language.clone(), support_score);
-            }
+        /// Only rank captures starting with this prefix (e.g. `function.`).
+        #[arg(long)]
+        prefix: Option<String>,
+    },
 
-            sort_truncate_display_hashmap(&language_support_scores, &order, limit);
+    /// Query the themes supporting a specific capture.
+    ThemesSupportingCapture {
+        #[arg(value_parser = parse_capture)]
+        capture: String,
+
+        #[arg(long)]
+        count: bool,
+
+        /// Match the capture name case-insensitively.
+        #[arg(long)]
+        ignore_case: bool,
+
+        /// Treat the capture as a glob pattern (e.g. `function.*`, `*.builtin`).
+        #[arg(long)]
+        glob: bool,
+    },
+    /// Query the languages using a specific capture.
+    LanguagesUsingCapture {
+        #[arg(value_parser = parse_capture)]
+        capture: String,
+
+        #[arg(long)]
+        count: bool,
+
+        /// Match the capture name case-insensitively.
+        #[arg(long)]
+        ignore_case: bool,
+
+        /// Treat the capture as a glob pattern (e.g. `function.*`, `*.builtin`).
+        #[arg(long)]
+        glob: bool,
+
+        /// Print how many times each language uses the capture, most first.
+        #[arg(long, conflicts_with = "count")]
+        with_counts: bool,
+
+        #[arg(long, default_value = "highlights")]
+        kind: QueryKind,
+    },
+
+    /// Roughly score and rank languages by the depth (average number of themes supporting each capture used in a language) and breadth (number of themes supporting at least one capture) of theme support.
+    /// The score is calculated as 7 * depth / number of captures + 3 * breadth.
+    /// The best languages will have a high score (order: desc) and the worst languages will have a low score (order: asc).
+    LanguagesByThemeSupport {
+        #[command(flatten)]
+        ranking: RankingOptions,
+    },
+    /// Query the themes supporting the most (order: desc) or least (order: asc) *USED* captures. Captures are considered used if they are used in any language extension.
+    ThemesByCaptureSupport {
+        #[command(flatten)]
+        ranking: RankingOptions,
+    },
+    /// Query the captures most often used in the same languages as a specific capture.
+    CaptureCooccurrence {
+        #[arg(value_parser = parse_capture)]
+        capture: String,
+
+        #[arg(short, long, default_value = "10")]
+        limit: usize,
+    },
+    /// Query the theme extensions containing themes that couldn't be parsed, and the offending files.
+    InvalidThemes {
+        #[arg(long)]
+        count: bool,
+    },
+    /// Query the grammar repositories referenced by the most (order: desc) or least (order: asc) extensions.
+    GrammarUsage {
+        #[command(flatten)]
+        ranking: RankingOptions,
+    },
+    /// Query the language servers declared by the most (order: desc) or least (order: asc) extensions.
+    LanguageServerUsage {
+        #[command(flatten)]
+        ranking: RankingOptions,
+
+        /// Print the languages served by each language server instead of the count.
+        #[arg(long)]
+        languages: bool,
+    },
+    /// Query the captures used by language extensions but supported by no theme extension.
+    UnthemedCaptures {
+        #[arg(long)]
+        count: bool,
+    },
+    /// Query the captures supported by theme extensions but used by no language extension.
+    UnusedThemeCaptures {
+        #[arg(long)]
+        count: bool,
+    },
+    /// Query the language extensions with highlight queries containing parse errors.
+    ValidateQueries,
+    /// Query the most (order: desc) or least (order: asc) used capture namespaces (e.g. `function` for `function.method`) in language extensions.
+    CaptureNamespaces {
+        #[command(flatten)]
+        ranking: RankingOptions,
+    },
+    /// Query the theme syntax styles with color values that aren't valid hex colors.
+    ValidateColors,
+    /// Query the captures defined more than once in the query files of a specific language extension.
+    DuplicateCaptures {
+        language: String,
+
+        #[arg(long, default_value = "highlights")]
+        kind: QueryKind,
+    },
+    /// Query the sorted, deduplicated captures used by a language extension or supported by a theme
+    /// extension.
+    CapturesOf {
+        id: String,
+
+        /// Query file to read captures from for language extensions.
+        #[arg(long, default_value = "highlights")]
+        kind: QueryKind,
+    },
+    /// Query the number of individual light and dark themes across all theme extensions.
+    ThemeAppearanceBreakdown,
+    /// Query the mean, median, minimum, and maximum number of distinct captures used per language.
+    CaptureStats,
+    /// Query the Git providers hosting the most (order: desc) or least (order: asc) extensions.
+    ExtensionsByProvider {
+        #[command(flatten)]
+        ranking: RankingOptions,
+    },
+    /// Query the language extensions where no language ships highlight queries.
+    MissingHighlights {
+        #[arg(long)]
+        count: bool,
+    },
+    /// Query the number of extensions using each manifest format.
+    ManifestFormats {
+        #[command(flatten)]
+        ranking: RankingOptions,
+    },
+    /// Query the themes with syntax captures that don't set any color or font style.
+    EmptyThemeCaptures {
+        #[arg(long)]
+        count: bool,
+    },
+    /// Query the languages whose first line pattern isn't a valid regular expression.
+    ValidateFirstLinePatterns,
+    /// Query the path suffixes claimed by more than one language.
+    SuffixConflicts {
+        #[arg(long)]
+        count: bool,
+    },
+    /// Query the themes supporting the highest (order: desc) or lowest (order: asc) percentage of *USED* captures. Captures are considered used if they are used in any language extension.
+    ThemeCoverage {
+        #[command(flatten)]
+        ranking: RankingOptions,
+    },
+    /// Query the language extensions using the most (order: desc) or fewest (order: asc) distinct captures.
+    LanguagesByCaptureCount {
+        #[command(flatten)]
+        ranking: RankingOptions,
+    },
+    /// Query the slash command extensions, along with the commands they provide.
+    SlashCommands {
+        #[arg(long)]
+        count: bool,
+    },
+    /// Query the context server extensions, along with the servers they provide.
+    ContextServers {
+        #[arg(long)]
+        count: bool,
+    },
+    /// Query the Jaccard similarity of the highlight captures of two language extensions, along
+    /// with the captures they share and the captures unique to each.
+    CaptureSimilarity { first: String, second: String },
+    /// Query how each grammar declared by a TOML or YAML manifest is pinned: to a commit, a rev, or
+    /// not at all (floating).
+    GrammarPins {
+        /// Only list floating grammar references.
+        #[arg(long)]
+        unpinned: bool,
+
+        /// Print the number of listed grammar references instead.
+        #[arg(long)]
+        count: bool,
+    },
+    /// Query the number of languages and their mean number of distinct highlight captures for each
+    /// major manifest version.
+    CaptureByVersion,
+    /// Query the syntax keys of each theme extension that match no capture used by any language,
+    /// which are likely typos or stale keys.
+    UnknownThemeKeys {
+        #[arg(long)]
+        count: bool,
+    },
+}
+
+#[allow(clippy::too_many_lines)]
+fn main() -> Result<()> {
+    let args: Cli = Cli::parse();
+
+    // Warnings about skipped extensions, themes, and language directories are only shown when
+    // verbose; `RUST_LOG` still takes precedence.
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(if args.verbose { "warn" } else { "error" }),
+    )
+    .init();
+    debug!("logger initialized");
+    // SAFETY: no git operations have been started yet, and none run on other threads.
+    unsafe { scan::set_git_timeout(Duration::from_secs(args.git_timeout))? };
+
+    let cache_dir = user_dirs::cache_dir()?.join("ts-ecosystem-zeta");
+    let extensions_scan_cache = cache_dir.join(scan_cache_file_name(&args.extensions_repo));
+
+    // Diffing only reads existing scan dumps, so there's no need to load or scan extensions.
+    if let Commands::Diff { old, new } = &args.command {
+        diff_scans(
+            &read_scan_dump(old)?.extensions,
+            &read_scan_dump(new)?.extensions,
+        );
+        return Ok(());
+    }
+
+    // Doctor always scans so it can report scan errors, reusing unchanged extensions from the cache
+    // unless `--refresh` or `--no-cache` asks for a full scan.
+    if let Commands::Doctor = &args.command {
+        let (scan_dump, errors) = match &args.local_dir {
+            Some(local_dir) => scan::local_extensions(local_dir)?,
+            None => scan::extensions(
+                &cache_dir,
+                &args.extensions_repo,
+                args.shallow,
+                if args.no_cache || args.refresh {
+                    Vec::new()
+                } else {
+                    read_scan_dump(&extensions_scan_cache)
+                        .map(|cached| cached.extensions)
+                        .unwrap_or_default()
+                },
+            )?,
+        };
+        print_doctor_report(&scan_dump.extensions, &errors);
+        return Ok(());
+    }
+
+    // Local directories are scanned directly, bypassing Git and the scan cache.
+    let scan_dump = match &args.local_dir {
+        Some(local_dir) => {
+            let (scan_dump, errors) = scan::local_extensions(local_dir)?;
+            warn_scan_errors(&errors);
+            scan_dump
+        }
+        None => load_extensions(&args, &cache_dir, &extensions_scan_cache)?,
+    };
+    report_failed_extensions(&scan_dump.failed);
+    let extensions = scan_dump.extensions;
+
+    match args.command {
+        Commands::Analyze {
+            json,
+            include_private,
+            query,
+        } => handle_query(
+            query,
+            include_private,
+            &without_builtin(extensions, args.exclude_builtin),
+            if json {
+                &OutputFormat::Json
+            } else {
+                &args.format
+            },
+        )?,
+        Commands::Find {
+            filters,
+            count,
+            json,
+        } => {
+            let format = if json {
+                &OutputFormat::Json
+            } else {
+                &args.format
+            };
+            let mut matching: Vec<Extension> = extensions
+                .into_iter()
+                .filter(|extension| filters.matches(extension))
+                .collect();
+            matching.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+
+            // JSON output carries whole extensions; every other format only lists their ids.
+            match format {
+                OutputFormat::Json if !count => {
+                    println!("{}", serde_json_lenient::to_string_pretty(&matching)?);
+                }
+                OutputFormat::Ndjson if !count => {
+                    for extension in &matching {
+                        println!("{}", serde_json_lenient::to_string(extension)?);
+                    }
+                }
+                _ => {
+                    let ids = matching.into_iter().map(|extension| extension.id).collect();
+                    display_query_result(&QueryResult::list_or_count(ids, count), format);
+                }
+            }
+        }
+        Commands::List { r#type, count } => {
+            let mut ids: Vec<&String> = extensions
+                .iter()
+                .filter(|extension| {
+                    r#type.as_ref().map_or(true, |extension_type| {
+                        extension_type.matches(&extension.r#type)
+                    })
+                })
+                .map(|extension| &extension.id)
+                .collect();
+            ids.sort_unstable();
+
+            println!("{}", count_or_list(ids, count));
+        }
+        Commands::Search { query, limit } => {
+            let mut matches: Vec<(usize, &Extension)> = extensions
+                .iter()
+                .filter_map(|extension| {
+                    fuzzy_score(&query, &extension.id)
+                        .max(fuzzy_score(&query, extension.metadata.name()))
+                        .map(|score| (score, extension))
+                })
+                .collect();
+            matches.sort_unstable_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.id.cmp(&b.1.id)));
+            if limit != 0 {
+                matches.truncate(limit);
+            }
+
+            for (_, extension) in matches {
+                println!("{} — {}", extension.id, extension.metadata.name());
+            }
+        }
+        Commands::Stats => print_stats(&extensions),
+        Commands::Export { out } => {
+            let extensions = without_builtin(extensions, args.exclude_builtin);
+            let capture_tables = CaptureTables::new(&extensions, &QueryKind::Highlights, false);
+            let mut used_captures: Vec<String> =
+                analysis::used_captures(&capture_tables.captures_by_language)
+                    .into_iter()
+                    .collect();
+            used_captures.sort_unstable();
+
+            fs::write(
+                &out,
+                serde_json_lenient::to_string_pretty(&serde_json_lenient::json!({
+                    "captures_by_language": capture_tables.captures_by_language,
+                    "supported_captures_by_theme": capture_tables.supported_captures_by_theme,
+                    "used_captures": used_captures,
+                }))?,
+            )?;
+            debug!("exported capture tables to {out:?}");
+        }
+        Commands::Diff { .. } => unreachable!("diff is handled before loading extensions"),
+        Commands::Doctor => unreachable!("doctor is handled before loading extensions"),
+        Commands::Show {
+            id,
+            summary,
+            fields,
+        } => {
+            let extension = find_extension(&extensions, &id)?;
+
+            if summary {
+                print_summary(extension);
+            } else if !fields.is_empty() {
+                let value = serde_json_lenient::to_value(extension)?;
+                for field in &fields {
+                    let Some(field_value) = project_field(&value, field) else {
+                        anyhow::bail!("unknown field '{field}' for extension '{id}'");
+                    };
+                    // Strings are printed bare so single fields can be used directly in scripts.
+                    let field_value = match field_value {
+                        serde_json_lenient::Value::String(string) => string.clone(),
+                        other => other.to_string(),
+                    };
+                    if fields.len() == 1 {
+                        println!("{field_value}");
+                    } else {
+                        println!("{field}: {field_value}");
+                    }
+                }
+            } else {
+                println!("{}", serde_json_lenient::to_string_pretty(extension)?);
+            }
+        }
+        Commands::ShowQuery { id, kind } => {
+            let extension = find_extension(&extensions, &id)?;
+            let ExtensionType::Language(language_extension) = &extension.r#type else {
+                anyhow::bail!("extension '{id}' isn't a language extension");
+            };
+
+            let queries: Vec<(&String, &String)> = language_extension
+                .languages
+                .iter()
+                .filter_map(|language| Some((&language.config.name, kind.source(language)?)))
+                .collect();
+            match queries.as_slice() {
+                [] => anyhow::bail!("extension '{id}' has no {kind:?} queries"),
+                [(_, source)] => print!("{source}"),
+                _ => {
+                    for (index, (language, source)) in queries.iter().enumerate() {
+                        if index > 0 {
+                            println!();
+                        }
+                        println!("; {language}");
+                        print!("{source}");
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Name of the scan cache file for an extensions repository. Other repositories get a name keyed by
+/// a stable hash of their URL (matching their clone directory) so caches of forks and mirrors don't
+/// collide, while the default repository keeps the original name.
+fn scan_cache_file_name(repository_url: &str) -> String {
+    if repository_url == scan::DEFAULT_EXTENSIONS_REPOSITORY {
+        return "extensions-scan-dump.json".to_string();
+    }
+
+    format!(
+        "extensions-scan-dump-{}.json",
+        scan::repository_hash(repository_url)
+    )
+}
+
+/// Load the scan dump from the scan cache, rescanning the extensions repository when the cache is
+/// missing, expired, or a refresh was requested.
+fn load_extensions(args: &Cli, cache_dir: &Path, extensions_scan_cache: &Path) -> Result<ScanDump> {
+    let cache_result = || read_scan_dump(extensions_scan_cache);
+
+    // Extensions from a previous scan are reused when their submodule commit hasn't changed.
+    let scan_result = |previous: Vec<Extension>| -> Result<ScanDump> {
+        let (scan_dump, errors) =
+            scan::extensions(cache_dir, &args.extensions_repo, args.shallow, previous)?;
+        warn_scan_errors(&errors);
+        Ok(scan_dump)
+    };
+
+    if args.no_cache {
+        return scan_result(Vec::new());
+    }
+
+    let cache_expired = args.max_age.is_some_and(|max_age| {
+        fs::metadata(extensions_scan_cache)
+            .and_then(|metadata| metadata.modified())
+            .map_or(true, |modified| {
+                SystemTime::now()
+                    .duration_since(modified)
+                    .unwrap_or_default()
+                    >= max_age
+            })
+    });
+    if cache_expired {
+        debug!("scan cache is older than the maximum age");
+    }
+
+    // `--refresh` re-parses everything, so a full rescan is always one flag away (e.g. after an
+    // update changed how extensions are parsed).
+    let (scan_dump, cache_hit) = if args.refresh {
+        (scan_result(Vec::new())?, false)
+    } else if cache_expired {
+        (
+            scan_result(
+                cache_result()
+                    .map(|cached| cached.extensions)
+                    .unwrap_or_default(),
+            )?,
+            false,
+        )
+    } else {
+        match cache_result() {
+            // Without a local clone there's no HEAD to compare against, so rescan.
+            Ok(cached) if args.refresh_if_changed => {
+                let head = scan::update_extensions_repository(cache_dir, &args.extensions_repo)?;
+                if head.is_some() && head == cached.head {
+                    (cached, true)
+                } else {
+                    debug!("extensions repository HEAD changed since the cached scan");
+                    (scan_result(cached.extensions)?, false)
+                }
+            }
+            Ok(cached) => (cached, true),
+            Err(_) => (scan_result(Vec::new())?, false),
+        }
+    };
+
+    if !cache_hit {
+        write_scan_dump(extensions_scan_cache, &scan_dump)?;
+    }
+
+    Ok(scan_dump)
+}
+
+fn warn_scan_errors(errors: &[(String, anyhow::Error)]) {
+    for (id, error) in errors {
+        warn!("skipping extension '{id}': {error:#}");
+    }
+}
+
+/// Tell the user that results are incomplete. The individual errors are only logged when verbose,
+/// so this is printed regardless, including when the scan comes from the cache.
+fn report_failed_extensions(failed: &[String]) {
+    match failed.len() {
+        0 => {}
+        1 => eprintln!("1 extension failed to scan; run `zeta doctor` for details"),
+        failed => eprintln!("{failed} extensions failed to scan; run `zeta doctor` for details"),
+    }
+}
+
+/// Read a scan dump, accepting dumps from before the repository HEAD was recorded (a bare list of
+/// extensions).
+fn read_scan_dump(path: &Path) -> Result<ScanDump> {
+    let contents = fs::read_to_string(path)?;
+    Ok(
+        serde_json_lenient::from_str::<ScanDump>(&contents).or_else(|_| {
+            serde_json_lenient::from_str::<Vec<Extension>>(&contents).map(|extensions| ScanDump {
+                head: None,
+                extensions,
+                failed: Vec::new(),
+            })
+        })?,
+    )
+}
+
+/// Write a scan dump to a temporary file next to `path` and rename it into place, so an interrupted
+/// or concurrent run never leaves a partially written dump behind.
+fn write_scan_dump(path: &Path, scan_dump: &ScanDump) -> Result<()> {
+    let mut temporary_name = path.file_name().unwrap_or_default().to_os_string();
+    temporary_name.push(format!(".{}.tmp", std::process::id()));
+    let temporary_path = path.with_file_name(temporary_name);
+
+    fs::write(&temporary_path, serde_json_lenient::to_string(scan_dump)?)
+        .with_context(|| format!("failed to write {}", temporary_path.display()))?;
+    if let Err(error) = fs::rename(&temporary_path, path) {
+        // Don't leave the temporary file behind if it can't be moved into place.
+        let _ = fs::remove_file(&temporary_path);
+        return Err(error).with_context(|| format!("failed to replace {}", path.display()));
+    }
+
+    Ok(())
+}
+
+fn diff_scans(old: &[Extension], new: &[Extension]) {
+    let old_by_id: HashMap<&String, &Extension> = old
+        .iter()
+        .map(|extension| (&extension.id, extension))
+        .collect();
+    let new_by_id: HashMap<&String, &Extension> = new
+        .iter()
+        .map(|extension| (&extension.id, extension))
+        .collect();
+
+    let mut added: Vec<&String> = new_by_id
+        .keys()
+        .filter(|id| !old_by_id.contains_key(*id))
+        .copied()
+        .collect();
+    let mut removed: Vec<&String> = old_by_id
+        .keys()
+        .filter(|id| !new_by_id.contains_key(*id))
+        .copied()
+        .collect();
+    let mut updated: Vec<(&String, &str, &str)> = new_by_id
+        .iter()
+        .filter_map(|(id, new_extension)| {
+            let old_version = old_by_id.get(id)?.metadata.version();
+            let new_version = new_extension.metadata.version();
+            (old_version != new_version).then_some((*id, old_version, new_version))
+        })
+        .collect();
+
+    added.sort_unstable();
+    removed.sort_unstable();
+    updated.sort_unstable();
+
+    println!("added ({}):", added.len());
+    for id in added {
+        println!("  {id}");
+    }
+    println!("removed ({}):", removed.len());
+    for id in removed {
+        println!("  {id}");
+    }
+    println!("updated ({}):", updated.len());
+    for (id, old_version, new_version) in updated {
+        println!("  {id}: {old_version} -> {new_version}");
+    }
+}
+
+fn print_summary(extension: &Extension) {
+    println!("id: {}", extension.id);
+    println!("name: {}", extension.metadata.name());
+    println!("version: {}", extension.metadata.version());
+    println!("type: {}", extension.r#type.tag());
+    if let Some(git_url) = &extension.git_url {
+        println!("git url: {git_url}");
+    }
+
+    match &extension.r#type {
+        ExtensionType::Language(language_extension) => {
+            println!("languages: {}", language_extension.languages.len());
+            for language in &language_extension.languages {
+                let queries: Vec<String> = QueryKind::value_variants()
+                    .iter()
+                    .filter(|kind| kind.source(language).is_some())
+                    .filter_map(|kind| Some(kind.to_possible_value()?.get_name().to_string()))
+                    .collect();
+                println!(
+                    "  - {} (grammar: {}): {}",
+                    language.config.name,
+                    language.config.grammar,
+                    if queries.is_empty() {
+                        "no queries".to_string()
+                    } else {
+                        queries.join(", ")
+                    }
+                );
+            }
+        }
+        ExtensionType::Theme(theme_extension) => {
+            println!("themes: {}", theme_extension.themes.len());
+        }
+        ExtensionType::SlashCommand | ExtensionType::ContextServer => {}
+    }
+}
+
+fn print_doctor_report(extensions: &[Extension], errors: &[(String, anyhow::Error)]) {
+    let mut scan_problems: HashMap<Option<ScanProblem>, Vec<String>> = HashMap::new();
+    for (id, error) in errors {
+        let problem = error.downcast_ref::<ScanProblem>().copied();
+        // The problem is already the section heading, so only print the underlying error.
+        let message: Vec<String> = error
+            .chain()
+            .skip(usize::from(problem.is_some()))
+            .map(ToString::to_string)
+            .collect();
+        scan_problems
+            .entry(problem)
+            .or_default()
+            .push(format!("{id}: {}", message.join(": ")));
+    }
+
+    let mut sections: Vec<(String, Vec<String>)> = [
+        ScanProblem::Submodule,
+        ScanProblem::MissingManifest,
+        ScanProblem::InvalidManifest,
+        ScanProblem::UnknownType,
+    ]
+    .into_iter()
+    .map(|problem| {
+        (
+            problem.to_string(),
+            scan_problems.remove(&Some(problem)).unwrap_or_default(),
+        )
+    })
+    .collect();
+    sections.push((
+        "other scan errors".to_string(),
+        scan_problems.remove(&None).unwrap_or_default(),
+    ));
+    sections.push((
+        "invalid themes".to_string(),
+        analysis::invalid_themes(extensions)
+            .into_iter()
+            .map(|(id, paths)| {
+                let file_names: Vec<String> = paths
+                    .iter()
+                    .map(|path| {
+                        path.file_name()
+                            .unwrap_or(path.as_os_str())
+                            .to_string_lossy()
+                            .to_string()
+                    })
+                    .collect();
+                format!("{id}: {}", file_names.join(", "))
+            })
+            .collect(),
+    ));
+    sections.push((
+        "highlight query parse errors".to_string(),
+        analysis::highlights_parse_errors(extensions)
+            .into_iter()
+            .map(|(id, language, line)| format!("{id} ({language}): line {line}"))
+            .collect(),
+    ));
+
+    for (heading, mut entries) in sections {
+        entries.sort_unstable();
+        println!("{heading} ({}):", entries.len());
+        for entry in entries {
+            println!("  {entry}");
+        }
+    }
+}
+
+/// Print the number of extensions with each distinct key, most common first.
+fn print_breakdown<'a>(label: &str, keys: impl Iterator<Item = &'a str>) {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for key in keys {
+        *counts.entry(key).or_default() += 1;
+    }
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+    println!("{label}:");
+    for (key, count) in counts {
+        println!("  {key}: {count}");
+    }
+}
+
+fn print_stats(extensions: &[Extension]) {
+    println!("extensions: {}", extensions.len());
+    print_breakdown(
+        "by type",
+        extensions.iter().map(|extension| extension.r#type.tag()),
+    );
+    print_breakdown(
+        "by manifest",
+        extensions.iter().map(|extension| extension.metadata.tag()),
+    );
+    print_breakdown(
+        "by git provider",
+        extensions
+            .iter()
+            .map(|extension| extension.git_provider.as_deref().unwrap_or("unknown")),
+    );
+    println!("grammars: {}", analysis::grammar_usage(extensions).len());
+    println!(
+        "captures: {}",
+        analysis::used_captures(&analysis::captures_by_language(
+            extensions,
+            &QueryKind::Highlights,
+            false
+        ))
+        .len()
+    );
+}
+
+/// Look up a dotted path (e.g. `metadata.name`) in a serialized extension. Enum wrappers (like the
+/// `TomlManifest` key around a manifest) are skipped when the path doesn't name them.
+fn project_field<'a>(
+    value: &'a serde_json_lenient::Value,
+    path: &str,
+) -> Option<&'a serde_json_lenient::Value> {
+    path.split('.').try_fold(value, |value, segment| {
+        let object = value.as_object()?;
+        object
+            .get(segment)
+            .or_else(|| match object.values().next() {
+                Some(variant) if object.len() == 1 => variant.as_object()?.get(segment),
+                _ => None,
+            })
+    })
+}
+
+/// Look up an extension by id, suggesting the closest id if there's no exact match.
+fn find_extension<'a>(extensions: &'a [Extension], id: &str) -> Result<&'a Extension> {
+    if let Some(extension) = extensions.iter().find(|extension| extension.id == id) {
+        return Ok(extension);
+    }
+
+    match extensions
+        .iter()
+        .min_by_key(|extension| levenshtein(&extension.id, id))
+    {
+        Some(closest) => anyhow::bail!(
+            "no extension with id '{id}' (did you mean '{}'?)",
+            closest.id
+        ),
+        None => anyhow::bail!("no extension with id '{id}'"),
+    }
+}
+
+/// Edit distance between two strings, used to suggest the closest extension id.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Score how well `query` fuzzily matches `candidate` (case-insensitive), or `None` if the query
+/// characters don't appear in order in the candidate. Consecutive matches and a matching prefix
+/// score higher.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<usize> {
+    let query = query.to_lowercase();
+    let candidate = candidate.to_lowercase();
+
+    let mut score = 0;
+    let mut candidate_chars = candidate.chars().enumerate();
+    let mut previous_index: Option<usize> = None;
+    for query_char in query.chars() {
+        let (index, _) = candidate_chars.find(|(_, c)| *c == query_char)?;
+        score += if previous_index.is_some_and(|previous| previous + 1 == index) {
+            3
+        } else {
+            1
+        };
+        previous_index = Some(index);
+    }
+
+    if candidate.starts_with(&query) {
+        score += 10;
+    } else if candidate.contains(&query) {
+        score += 5;
+    }
+
+    Some(score)
+}
+
+fn count_or_list<T: ToString>(items: Vec<T>, count: bool) -> String {
+    if count {
+        items.len().to_string()
+    } else {
+        items
+            .into_iter()
+            .map(|item| item.to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+fn sort_truncate_hashmap<'a>(
+    map: &'a HashMap<String, usize>,
+    options: &RankingOptions,
+) -> Vec<(&'a String, &'a usize)> {
+    let mut sorted_map: Vec<(&String, &usize)> = map
+        .iter()
+        .filter(|(_, value)| **value >= options.min_count)
+        .collect();
+
+    // Ties are broken by key so output is stable between runs.
+    match (&options.sort, &options.order) {
+        (SortKey::Count, SortOrder::Asc | SortOrder::Ascending) => {
+            sorted_map.sort_unstable_by(|a, b| a.1.cmp(b.1).then_with(|| a.0.cmp(b.0)));
+        }
+        (SortKey::Count, SortOrder::Desc | SortOrder::Descending) | (SortKey::Name, _) => {
+            sorted_map.sort_unstable_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        }
+    }
+
+    if options.limit != 0 {
+        sorted_map.truncate(options.limit);
+    }
+
+    if let SortKey::Name = options.sort {
+        match options.order {
+            SortOrder::Asc | SortOrder::Ascending => sorted_map.sort_unstable_by_key(|a| a.0),
+            SortOrder::Desc | SortOrder::Descending => {
+                sorted_map.sort_unstable_by(|a, b| b.0.cmp(a.0));
+            }
+        }
+    }
+
+    sorted_map
+}
+
+/// Ids of the extensions of the given type, each followed by the names its manifest provides.
+/// JSON manifests can't declare slash commands or context servers, so those only list the id.
+fn extensions_providing(
+    extensions: &[Extension],
+    r#type: &BasicExtensionType,
+    names: impl Fn(&TomlManifest) -> Vec<String>,
+) -> Vec<String> {
+    let mut providing: Vec<String> = extensions
+        .iter()
+        .filter(|extension| r#type.matches(&extension.r#type))
+        .map(|extension| {
+            let mut provided = match &extension.metadata {
+                ExtensionMetadata::TomlManifest(manifest)
+                | ExtensionMetadata::YamlManifest(manifest) => names(manifest),
+                ExtensionMetadata::JsonManifest(_) => Vec::new(),
+            };
+            if provided.is_empty() {
+                extension.id.clone()
+            } else {
+                provided.sort();
+                format!("{}: {}", extension.id, provided.join(", "))
+            }
+        })
+        .collect();
+    providing.sort();
+    providing
+}
+
+/// Parse a capture argument, accepting it with or without the leading `@` used in query files.
+fn parse_capture(capture: &str) -> Result<String, String> {
+    match capture.strip_prefix('@').unwrap_or(capture) {
+        "" => Err("capture name can't be empty".to_string()),
+        capture => Ok(capture.to_string()),
+    }
+}
+
+/// Build a predicate matching capture names against the requested capture, either exactly or as a
+/// glob pattern, and optionally ignoring case.
+fn capture_matcher(capture: &str, glob: bool, ignore_case: bool) -> Result<impl Fn(&str) -> bool> {
+    let glob = glob
+        .then(|| {
+            // `function.*` also matches `function` itself, the capture it's nested under.
+            let mut patterns = vec![capture];
+            patterns.extend(capture.strip_suffix(".*"));
+
+            let mut glob_set = GlobSetBuilder::new();
+            for pattern in patterns {
+                glob_set.add(
+                    GlobBuilder::new(pattern)
+                        .case_insensitive(ignore_case)
+                        .build()?,
+                );
+            }
+            glob_set.build()
+        })
+        .transpose()
+        .with_context(|| format!("invalid capture glob '{capture}'"))?;
+    let capture = capture.to_string();
+
+    Ok(move |name: &str| match &glob {
+        Some(glob) => glob.is_match(name),
+        None if ignore_case => name.eq_ignore_ascii_case(&capture),
+        None => name == capture,
+    })
+}
+
+/// Leave the builtin extensions out of `extensions` if `exclude` is set (`--exclude-builtin`).
+fn without_builtin(extensions: Vec<Extension>, exclude: bool) -> Vec<Extension> {
+    extensions
+        .into_iter()
+        .filter(|extension| !(exclude && extension.builtin))
+        .collect()
+}
+
+/// Keep only the entries whose key starts with `prefix`, if one was provided.
+fn retain_prefix(map: &mut HashMap<String, usize>, prefix: Option<&str>) {
+    if let Some(prefix) = prefix {
+        map.retain(|key, _| key.starts_with(prefix));
+    }
+}
+
+/// Result of an analysis query, rendered once according to the output format.
+enum QueryResult {
+    /// Ranked `name: count` entries, and the number of distinct entries before filtering if
+    /// requested.
+    Ranking(Vec<(String, usize)>, Option<usize>),
+    /// Ranked `name: percentage` entries, and the number of distinct entries before filtering if
+    /// requested.
+    Percentages(Vec<(String, f64)>, Option<usize>),
+    /// `name: count` entries of a breakdown, the total of all counts, and what's counted (e.g.
+    /// `themes`).
+    Tally(Vec<(String, usize)>, usize, &'static str),
+    /// Summary statistics over the captures used per language.
+    CaptureStats(analysis::CaptureStats),
+    /// Plain list of items.
+    List(Vec<String>),
+    /// Number of matching items.
+    Count(usize),
+}
+
+impl QueryResult {
+    fn ranking(map: &HashMap<String, usize>, options: &RankingOptions) -> Self {
+        QueryResult::Ranking(
+            sort_truncate_hashmap(map, options)
+                .into_iter()
+                .map(|(key, value)| (key.clone(), *value))
+                .collect(),
+            options.with_total.then_some(map.len()),
+        )
+    }
+
+    fn list_or_count(items: Vec<String>, count: bool) -> Self {
+        if count {
+            QueryResult::Count(items.len())
+        } else {
+            QueryResult::List(items)
+        }
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn display_query_result(result: &QueryResult, format: &OutputFormat) {
+    match (result, format) {
+        (QueryResult::Ranking(ranking, total), format) => {
+            display_ranking(ranking, total.map(|total| (total, "distinct")), format);
+        }
+        (QueryResult::Tally(tally, total, unit), format) => {
+            display_ranking(tally, Some((*total, unit)), format);
+        }
+        (QueryResult::Percentages(percentages, total), format) => {
+            display_percentages(percentages, *total, format);
+        }
+        (QueryResult::CaptureStats(stats), format) => display_capture_stats(stats, format),
+        (QueryResult::List(items), OutputFormat::Table) => {
+            let name_width = items
+                .iter()
+                .map(|item| item.chars().count())
+                .chain(["name".len()])
+                .max()
+                .unwrap_or_default();
+
+            println!("name");
+            println!("{}", "-".repeat(name_width));
+            for item in items {
+                println!("{item}");
+            }
+        }
+        (QueryResult::List(items), OutputFormat::Text) => {
+            for item in items {
+                println!("{item}");
+            }
+        }
+        (QueryResult::List(items), OutputFormat::Csv) => {
+            println!("name");
+            for item in items {
+                println!("{}", csv_field(item));
+            }
+        }
+        (QueryResult::List(items), OutputFormat::Json) => {
+            println!("{:#}", serde_json_lenient::json!(items));
+        }
+        (QueryResult::Count(count), OutputFormat::Text | OutputFormat::Table) => {
+            println!("{count}");
+        }
+        (QueryResult::Count(count), OutputFormat::Csv) => println!("count\n{count}"),
+        (QueryResult::Count(count), OutputFormat::Json) => {
+            println!("{:#}", serde_json_lenient::json!({ "count": count }));
+        }
+        (QueryResult::Count(count), OutputFormat::Ndjson) => {
+            println!("{}", serde_json_lenient::json!({ "count": count }));
+        }
+        (QueryResult::List(items), OutputFormat::Ndjson) => {
+            for item in items {
+                println!("{}", serde_json_lenient::json!(item));
+            }
+        }
+    }
+}
+
+/// Render `name: count` entries, followed by the total (and what it counts, e.g. `distinct`) if
+/// there is one.
+fn display_ranking(
+    ranking: &[(String, usize)],
+    total: Option<(usize, &str)>,
+    format: &OutputFormat,
+) {
+    match format {
+        OutputFormat::Text => {
+            for (key, value) in ranking {
+                println!("{key}: {value}");
+            }
+            if let Some((total, unit)) = total {
+                println!("total: {total} {unit}");
+            }
+        }
+        // The total is left out of CSV output to keep every row a `name,count` record.
+        OutputFormat::Csv => {
+            println!("name,count");
+            for (key, value) in ranking {
+                println!("{},{value}", csv_field(key));
+            }
+        }
+        OutputFormat::Json => {
+            let entries: Vec<serde_json_lenient::Value> = ranking
+                .iter()
+                .map(|(key, value)| serde_json_lenient::json!({ "name": key, "count": value }))
+                .collect();
+            match total {
+                Some((total, _)) => println!(
+                    "{:#}",
+                    serde_json_lenient::json!({ "entries": entries, "total": total })
+                ),
+                None => println!("{:#}", serde_json_lenient::Value::Array(entries)),
+            }
+        }
+        OutputFormat::Table => {
+            let name_width = ranking
+                .iter()
+                .map(|(key, _)| key.chars().count())
+                .chain(["name".len()])
+                .max()
+                .unwrap_or_default();
+            let count_width = ranking
+                .iter()
+                .map(|(_, value)| value.to_string().len())
+                .chain(["count".len()])
+                .max()
+                .unwrap_or_default();
+
+            println!("{:<name_width$}  {:>count_width$}", "name", "count");
+            println!("{}  {}", "-".repeat(name_width), "-".repeat(count_width));
+            for (key, value) in ranking {
+                println!("{key:<name_width$}  {value:>count_width$}");
+            }
+            if let Some((total, unit)) = total {
+                println!("total: {total} {unit}");
+            }
+        }
+        // Like CSV, the total is left out so every line is a `name`/`count` record.
+        OutputFormat::Ndjson => {
+            for (key, value) in ranking {
+                println!(
+                    "{}",
+                    serde_json_lenient::json!({ "name": key, "count": value })
+                );
+            }
+        }
+    }
+}
+
+/// Capture statistics are a single record: `name: value` lines in text output, `name`/`value`
+/// columns in table output, a header and one row in CSV, and one object in JSON and ndjson.
+fn display_capture_stats(stats: &analysis::CaptureStats, format: &OutputFormat) {
+    let fields = stats.fields();
+    match format {
+        OutputFormat::Text => {
+            for (name, value) in &fields {
+                println!("{name}: {value}");
+            }
+        }
+        OutputFormat::Csv => {
+            let names: Vec<&str> = fields.iter().map(|(name, _)| *name).collect();
+            let values: Vec<String> = fields.iter().map(|(_, value)| csv_field(value)).collect();
+            println!("{}", names.join(","));
+            println!("{}", values.join(","));
+        }
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json_lenient::to_string_pretty(stats).expect("capture stats should serialize")
+        ),
+        OutputFormat::Table => {
+            let name_width = fields
+                .iter()
+                .map(|(name, _)| name.len())
+                .chain(["name".len()])
+                .max()
+                .unwrap_or_default();
+            let value_width = fields
+                .iter()
+                .map(|(_, value)| value.chars().count())
+                .chain(["value".len()])
+                .max()
+                .unwrap_or_default();
+
+            println!("{:<name_width$}  {:>value_width$}", "name", "value");
+            println!("{}  {}", "-".repeat(name_width), "-".repeat(value_width));
+            for (name, value) in &fields {
+                println!("{name:<name_width$}  {value:>value_width$}");
+            }
+        }
+        OutputFormat::Ndjson => println!(
+            "{}",
+            serde_json_lenient::to_string(stats).expect("capture stats should serialize")
+        ),
+    }
+}
+
+/// Percentages are rendered like rankings, with one decimal and a `%` sign in text output.
+fn display_percentages(percentages: &[(String, f64)], total: Option<usize>, format: &OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            for (key, percentage) in percentages {
+                println!("{key}: {percentage:.1}%");
+            }
+            if let Some(total) = total {
+                println!("total: {total} distinct");
+            }
+        }
+        // The total is left out of CSV and ndjson output, like for rankings.
+        OutputFormat::Csv => {
+            println!("name,percentage");
+            for (key, percentage) in percentages {
+                println!("{},{percentage:.1}", csv_field(key));
+            }
+        }
+        OutputFormat::Json => {
+            let entries: Vec<serde_json_lenient::Value> = percentages
+                .iter()
+                .map(|(key, percentage)| {
+                    serde_json_lenient::json!({ "name": key, "percentage": percentage })
+                })
+                .collect();
+            match total {
+                Some(total) => println!(
+                    "{:#}",
+                    serde_json_lenient::json!({ "entries": entries, "total": total })
+                ),
+                None => println!("{:#}", serde_json_lenient::Value::Array(entries)),
+            }
+        }
+        OutputFormat::Table => {
+            let rows: Vec<(&String, String)> = percentages
+                .iter()
+                .map(|(key, percentage)| (key, format!("{percentage:.1}%")))
+                .collect();
+            let name_width = rows
+                .iter()
+                .map(|(key, _)| key.chars().count())
+                .chain(["name".len()])
+                .max()
+                .unwrap_or_default();
+            let percentage_width = rows
+                .iter()
+                .map(|(_, percentage)| percentage.len())
+                .chain(["percentage".len()])
+                .max()
+                .unwrap_or_default();
+
+            println!(
+                "{:<name_width$}  {:>percentage_width$}",
+                "name", "percentage"
+            );
+            println!(
+                "{}  {}",
+                "-".repeat(name_width),
+                "-".repeat(percentage_width)
+            );
+            for (key, percentage) in rows {
+                println!("{key:<name_width$}  {percentage:>percentage_width$}");
+            }
+            if let Some(total) = total {
+                println!("total: {total} distinct");
+            }
+        }
+        OutputFormat::Ndjson => {
+            for (key, percentage) in percentages {
+                println!(
+                    "{}",
+                    serde_json_lenient::json!({ "name": key, "percentage": percentage })
+                );
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn handle_query(
+    query: AnalysisQuery,
+    include_private: bool,
+    extensions: &[Extension],
+    format: &OutputFormat,
+) -> Result<()> {
+    // Captures used by languages are collected from the query file selected by the query.
+    let kind = match &query {
+        AnalysisQuery::CapturesByUsage { kind, .. }
+        | AnalysisQuery::LanguagesUsingCapture { kind, .. }
+        | AnalysisQuery::DuplicateCaptures { kind, .. }
+        | AnalysisQuery::CapturesOf { kind, .. } => kind.clone(),
+        AnalysisQuery::InjectionCapturesByUsage { .. } => QueryKind::Injections,
+        AnalysisQuery::BracketCaptures { .. } => QueryKind::Brackets,
+        AnalysisQuery::OutlineCaptures { .. } => QueryKind::Outline,
+        _ => QueryKind::Highlights,
+    };
+
+    let CaptureTables {
+        captures_by_language,
+        supported_captures_by_theme,
+    } = CaptureTables::new(extensions, &kind, include_private);
+
+    let result = match query {
+        AnalysisQuery::CapturesByUsage {
+            ranking, prefix, ..
+        } => {
+            let mut capture_counts = analysis::count_capture_usage(&captures_by_language);
+            retain_prefix(&mut capture_counts, prefix.as_deref());
+
+            QueryResult::ranking(&capture_counts, &ranking)
+        }
+        AnalysisQuery::InjectionCapturesByUsage { ranking }
+        | AnalysisQuery::BracketCaptures { ranking }
+        | AnalysisQuery::OutlineCaptures {
+            ranking,
+            missing_core: false,
+        } => {
+            let capture_counts = analysis::count_capture_usage(&captures_by_language);
+
+            QueryResult::ranking(&capture_counts, &ranking)
+        }
+        AnalysisQuery::OutlineCaptures {
+            missing_core: true, ..
+        } => QueryResult::List(
+            analysis::outline_queries_without_core_captures(extensions)
+                .into_iter()
+                .map(|(id, language)| format!("{id}: {language}"))
+                .collect(),
+        ),
+        AnalysisQuery::CapturesByThemeSupport { ranking, prefix } => {
+            let mut capture_counts: HashMap<String, usize> = HashMap::new();
+            for captures in supported_captures_by_theme.values() {
+                for capture in captures {
+                    *capture_counts.entry(capture.clone()).or_default() += 1;
+                }
+            }
+            retain_prefix(&mut capture_counts, prefix.as_deref());
+
+            QueryResult::ranking(&capture_counts, &ranking)
+        }
+
+        AnalysisQuery::ThemesSupportingCapture {
+            capture,
+            count,
+            ignore_case,
+            glob,
+        } => {
+            let matches = capture_matcher(&capture, glob, ignore_case)?;
+            let mut themes_with_support: Vec<String> = supported_captures_by_theme
+                .iter()
+                .filter(|(_, supported_captures)| {
+                    supported_captures.iter().any(|name| matches(name))
+                })
+                .map(|(theme, _)| theme.clone())
+                .collect();
+            themes_with_support.sort_unstable();
+
+            QueryResult::list_or_count(themes_with_support, count)
+        }
+        AnalysisQuery::LanguagesUsingCapture {
+            capture,
+            with_counts: true,
+            ignore_case,
+            glob,
+            ..
+        } => {
+            let matches = capture_matcher(&capture, glob, ignore_case)?;
+            let capture_counts: HashMap<String, usize> = captures_by_language
+                .iter()
+                .filter_map(|(language, captures)| {
+                    let occurrences = captures.iter().filter(|name| matches(name)).count();
+                    (occurrences > 0).then(|| (language.clone(), occurrences))
+                })
+                .collect();
+
+            QueryResult::ranking(
+                &capture_counts,
+                &RankingOptions {
+                    order: SortOrder::Desc,
+                    limit: 0,
+                    min_count: 0,
+                    with_total: false,
+                    sort: SortKey::Count,
+                },
+            )
+        }
+        AnalysisQuery::LanguagesUsingCapture {
+            capture,
+            count,
+            ignore_case,
+            glob,
+            ..
+        } => {
+            let matches = capture_matcher(&capture, glob, ignore_case)?;
+            // Captures are keyed by extension id, so this sorts the languages by id.
+            let mut languages_using_capture: Vec<String> = captures_by_language
+                .iter()
+                .filter_map(|(language, captures)| {
+                    if captures.iter().any(|name| matches(name)) {
+                        Some(language.clone())
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            languages_using_capture.sort_unstable();
+
+            QueryResult::list_or_count(languages_using_capture, count)
+        }
+
+        AnalysisQuery::LanguagesByThemeSupport { ranking } => {
+            let language_support_scores = analysis::language_theme_support_scores(
+                &captures_by_language,
+                &supported_captures_by_theme,
+            );
+
+            QueryResult::ranking(&language_support_scores, &ranking)
         }
 
-        AnalysisQuery::ThemesByCaptureSupport { order, limit } => {
-            let used_captures: HashSet<String> =
-                captures_by_language.values().flatten().cloned().collect();
+        AnalysisQuery::ThemesByCaptureSupport { ranking } => {
+            let used_captures = analysis::used_captures(&captures_by_language);
 
-            let themes_by_used_captures_support: Vec<(&String, usize)> =
+            let themes_by_used_captures_support: HashMap<String, usize> =
                 supported_captures_by_theme
                     .iter()
                     .map(|(theme, captures)| {
                         (
-                            theme,
+                            theme.clone(),
                             captures
                                 .iter()
                                 .filter(|capture| used_captures.contains(*capture))
@@ -506,24 +2016,879 @@ fn handle_query(query: AnalysisQuery, extensions: Vec<Extension>) {
                     })
                     .collect();
 
-            let mut sorted_themes: Vec<(&String, usize)> = themes_by_used_captures_support;
+            QueryResult::ranking(&themes_by_used_captures_support, &ranking)
+        }
+
+        AnalysisQuery::CaptureCooccurrence { capture, limit } => {
+            let mut cooccurrence_counts: HashMap<String, usize> = HashMap::new();
+
+            for captures in captures_by_language
+                .values()
+                .filter(|captures| captures.contains(&capture))
+            {
+                let other_captures: HashSet<&String> = captures
+                    .iter()
+                    .filter(|other_capture| **other_capture != capture)
+                    .collect();
+
+                for other_capture in other_captures {
+                    *cooccurrence_counts
+                        .entry(other_capture.clone())
+                        .or_default() += 1;
+                }
+            }
+
+            QueryResult::ranking(
+                &cooccurrence_counts,
+                &RankingOptions {
+                    order: SortOrder::Desc,
+                    limit,
+                    min_count: 0,
+                    with_total: false,
+                    sort: SortKey::Count,
+                },
+            )
+        }
+
+        AnalysisQuery::InvalidThemes { count } => {
+            let invalid_themes: Vec<String> = analysis::invalid_themes(extensions)
+                .iter()
+                .map(|(id, paths)| {
+                    let file_names: Vec<String> = paths
+                        .iter()
+                        .map(|path| {
+                            path.file_name()
+                                .unwrap_or(path.as_os_str())
+                                .to_string_lossy()
+                                .to_string()
+                        })
+                        .collect();
+                    format!("{id}: {}", file_names.join(", "))
+                })
+                .collect();
+
+            QueryResult::list_or_count(invalid_themes, count)
+        }
+
+        AnalysisQuery::GrammarUsage { ranking } => {
+            let grammar_counts = analysis::grammar_usage(extensions);
+
+            QueryResult::ranking(&grammar_counts, &ranking)
+        }
+
+        AnalysisQuery::LanguageServerUsage { ranking, languages } => {
+            let language_server_counts = analysis::language_server_usage(extensions);
+
+            if languages {
+                let languages_by_server = analysis::language_server_languages(extensions);
+                QueryResult::List(
+                    sort_truncate_hashmap(&language_server_counts, &ranking)
+                        .into_iter()
+                        .map(|(language_server, _)| {
+                            format!(
+                                "{language_server}: {}",
+                                languages_by_server
+                                    .get(language_server)
+                                    .map(|languages| languages.join(", "))
+                                    .unwrap_or_default()
+                            )
+                        })
+                        .collect(),
+                )
+            } else {
+                QueryResult::ranking(&language_server_counts, &ranking)
+            }
+        }
+
+        AnalysisQuery::UnthemedCaptures { count } => {
+            let themed_captures = analysis::themed_captures(&supported_captures_by_theme);
+            let mut unthemed_captures: Vec<String> = analysis::used_captures(&captures_by_language)
+                .into_iter()
+                .filter(|capture| !themed_captures.contains(capture))
+                .collect();
+            unthemed_captures.sort_unstable();
+
+            QueryResult::list_or_count(unthemed_captures, count)
+        }
+
+        AnalysisQuery::UnusedThemeCaptures { count } => {
+            let used_captures = analysis::used_captures(&captures_by_language);
+            let mut unused_theme_captures: Vec<String> =
+                analysis::themed_captures(&supported_captures_by_theme)
+                    .into_iter()
+                    .filter(|capture| !used_captures.contains(capture))
+                    .collect();
+            unused_theme_captures.sort_unstable();
+
+            QueryResult::list_or_count(unused_theme_captures, count)
+        }
+
+        AnalysisQuery::ValidateQueries => QueryResult::List(
+            analysis::highlights_parse_errors(extensions)
+                .into_iter()
+                .map(|(id, language, line)| {
+                    format!("{id} ({language}): parse error near line {line}")
+                })
+                .collect(),
+        ),
+
+        AnalysisQuery::CaptureNamespaces { ranking } => {
+            let namespace_counts = analysis::capture_namespace_usage(&captures_by_language);
+
+            QueryResult::ranking(&namespace_counts, &ranking)
+        }
+
+        AnalysisQuery::ValidateColors => QueryResult::List(
+            analysis::invalid_theme_colors(extensions)
+                .into_iter()
+                .map(|(id, theme, capture, color)| format!("{id} ({theme}): {capture}: {color}"))
+                .collect(),
+        ),
+
+        AnalysisQuery::DuplicateCaptures { language, .. } => {
+            let extension = find_extension(extensions, &language)?;
+            if !matches!(extension.r#type, ExtensionType::Language(_)) {
+                anyhow::bail!(
+                    "extension '{language}' is a {} extension, not a language extension",
+                    extension.r#type.tag()
+                );
+            }
+
+            let mut capture_counts: HashMap<String, usize> = HashMap::new();
+            for capture in captures_by_language.get(&language).into_iter().flatten() {
+                *capture_counts.entry(capture.clone()).or_default() += 1;
+            }
+
+            QueryResult::ranking(
+                &capture_counts,
+                &RankingOptions {
+                    order: SortOrder::Desc,
+                    limit: 0,
+                    min_count: 2,
+                    with_total: false,
+                    sort: SortKey::Count,
+                },
+            )
+        }
+
+        AnalysisQuery::CapturesOf { id, .. } => {
+            let extension = find_extension(extensions, &id)?;
 
-            match order {
-                SortOrder::Asc | SortOrder::Ascending => {
-                    sorted_themes.sort_unstable_by(|a, b| a.1.cmp(&b.1));
+            match &extension.r#type {
+                ExtensionType::Language(_) => {
+                    let mut captures = captures_by_language.get(&id).cloned().unwrap_or_default();
+                    captures.sort_unstable();
+                    captures.dedup();
+                    QueryResult::List(captures)
                 }
-                SortOrder::Desc | SortOrder::Descending => {
-                    sorted_themes.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+                ExtensionType::Theme(theme_extension) => {
+                    QueryResult::List(theme_extension.syntax_capture_names())
                 }
+                _ => anyhow::bail!(
+                    "extension '{id}' is a {} extension, not a language or theme extension",
+                    extension.r#type.tag()
+                ),
+            }
+        }
+
+        AnalysisQuery::ThemeAppearanceBreakdown => {
+            let (light, dark) = analysis::theme_appearance_counts(extensions);
+
+            QueryResult::Tally(
+                vec![("light".to_string(), light), ("dark".to_string(), dark)],
+                light + dark,
+                "themes",
+            )
+        }
+
+        AnalysisQuery::CaptureStats => match analysis::capture_stats(&captures_by_language) {
+            Some(stats) => QueryResult::CaptureStats(stats),
+            None => QueryResult::List(Vec::new()),
+        },
+
+        AnalysisQuery::ExtensionsByProvider { ranking } => {
+            let mut provider_counts: HashMap<String, usize> = HashMap::new();
+            for extension in extensions {
+                let provider = extension.git_provider.as_deref().unwrap_or("unknown");
+                *provider_counts.entry(provider.to_string()).or_default() += 1;
             }
 
-            if limit != 0 {
-                sorted_themes.truncate(limit);
+            QueryResult::ranking(&provider_counts, &ranking)
+        }
+
+        AnalysisQuery::MissingHighlights { count } => {
+            let mut missing_highlights: Vec<String> = extensions
+                .iter()
+                .filter_map(|extension| {
+                    let ExtensionType::Language(language_extension) = &extension.r#type else {
+                        return None;
+                    };
+                    if language_extension
+                        .languages
+                        .iter()
+                        .any(|language| language.highlights_queries.is_some())
+                    {
+                        return None;
+                    }
+
+                    // Extensions without scanned languages only have the grammars in their
+                    // manifest.
+                    let mut grammars: Vec<&String> = if language_extension.languages.is_empty() {
+                        extension.metadata.grammar_names()
+                    } else {
+                        language_extension
+                            .languages
+                            .iter()
+                            .map(|language| &language.config.grammar)
+                            .collect()
+                    };
+                    grammars.sort_unstable();
+                    grammars.dedup();
+
+                    Some(format!(
+                        "{}: {}",
+                        extension.id,
+                        grammars
+                            .iter()
+                            .map(|grammar| grammar.as_str())
+                            .collect::<Vec<&str>>()
+                            .join(", ")
+                    ))
+                })
+                .collect();
+            missing_highlights.sort_unstable();
+
+            QueryResult::list_or_count(missing_highlights, count)
+        }
+
+        AnalysisQuery::ManifestFormats { ranking } => {
+            let mut format_counts: HashMap<String, usize> = HashMap::new();
+            for extension in extensions {
+                *format_counts
+                    .entry(extension.metadata.tag().to_string())
+                    .or_default() += 1;
             }
 
-            for (theme, count) in sorted_themes {
-                println!("{theme}: {count}");
+            QueryResult::ranking(&format_counts, &ranking)
+        }
+
+        AnalysisQuery::EmptyThemeCaptures { count } => QueryResult::list_or_count(
+            analysis::empty_theme_captures(extensions)
+                .into_iter()
+                .map(|(id, theme, empty_captures)| format!("{id} ({theme}): {empty_captures}"))
+                .collect(),
+            count,
+        ),
+
+        AnalysisQuery::ValidateFirstLinePatterns => QueryResult::List(
+            analysis::invalid_first_line_patterns(extensions)
+                .into_iter()
+                .map(|(id, language, error)| format!("{id} ({language}): {error}"))
+                .collect(),
+        ),
+
+        AnalysisQuery::SuffixConflicts { count } => QueryResult::list_or_count(
+            analysis::path_suffix_conflicts(extensions)
+                .into_iter()
+                .map(|(suffix, claimants)| format!("{suffix}: {}", claimants.join(", ")))
+                .collect(),
+            count,
+        ),
+
+        AnalysisQuery::ThemeCoverage { ranking } => {
+            let used_captures = analysis::used_captures(&captures_by_language);
+
+            let supported_used_captures: HashMap<String, usize> = supported_captures_by_theme
+                .iter()
+                .map(|(theme, captures)| {
+                    (
+                        theme.clone(),
+                        captures
+                            .iter()
+                            .filter(|capture| used_captures.contains(*capture))
+                            .count(),
+                    )
+                })
+                .collect();
+
+            // Every theme is divided by the same number of used captures, so ranking by the
+            // supported count ranks by coverage.
+            QueryResult::Percentages(
+                sort_truncate_hashmap(&supported_used_captures, &ranking)
+                    .into_iter()
+                    .map(|(theme, supported)| {
+                        #[allow(clippy::cast_precision_loss)]
+                        let coverage = if used_captures.is_empty() {
+                            0.0
+                        } else {
+                            *supported as f64 / used_captures.len() as f64 * 100.0
+                        };
+                        (theme.clone(), coverage)
+                    })
+                    .collect(),
+                ranking.with_total.then_some(supported_used_captures.len()),
+            )
+        }
+
+        AnalysisQuery::LanguagesByCaptureCount { ranking } => {
+            let capture_counts = analysis::distinct_capture_counts(&captures_by_language);
+
+            QueryResult::ranking(&capture_counts, &ranking)
+        }
+
+        AnalysisQuery::SlashCommands { count } => QueryResult::list_or_count(
+            extensions_providing(extensions, &BasicExtensionType::SlashCommand, |manifest| {
+                manifest
+                    .slash_commands
+                    .iter()
+                    .flat_map(|commands| commands.keys().cloned())
+                    .collect()
+            }),
+            count,
+        ),
+        AnalysisQuery::ContextServers { count } => QueryResult::list_or_count(
+            extensions_providing(extensions, &BasicExtensionType::ContextServer, |manifest| {
+                manifest
+                    .context_servers
+                    .iter()
+                    .flat_map(|servers| servers.keys().cloned())
+                    .collect()
+            }),
+            count,
+        ),
+
+        AnalysisQuery::CaptureSimilarity { first, second } => {
+            for id in [&first, &second] {
+                let extension = find_extension(extensions, id)?;
+                if !matches!(extension.r#type, ExtensionType::Language(_)) {
+                    anyhow::bail!(
+                        "extension '{id}' is a {} extension, not a language extension",
+                        extension.r#type.tag()
+                    );
+                }
             }
+
+            let similarity = analysis::capture_similarity(
+                captures_by_language
+                    .get(&first)
+                    .map_or(Default::default(), Vec::as_slice),
+                captures_by_language
+                    .get(&second)
+                    .map_or(Default::default(), Vec::as_slice),
+            );
+
+            QueryResult::List(vec![
+                format!("similarity: {:.2}", similarity.score),
+                format!("shared: {}", similarity.shared.join(", ")),
+                format!("only in {first}: {}", similarity.only_first.join(", ")),
+                format!("only in {second}: {}", similarity.only_second.join(", ")),
+            ])
+        }
+
+        AnalysisQuery::GrammarPins { unpinned, count } => QueryResult::list_or_count(
+            analysis::grammar_pins(extensions)
+                .into_iter()
+                .filter(|(_, _, pin)| !unpinned || matches!(pin, GrammarPin::Floating))
+                .map(|(id, grammar, pin)| format!("{id}: {grammar} ({pin})"))
+                .collect(),
+            count,
+        ),
+
+        AnalysisQuery::CaptureByVersion => QueryResult::List(
+            analysis::capture_counts_by_major_version(extensions, &captures_by_language)
+                .into_iter()
+                .map(|(major, languages, mean)| {
+                    format!("v{major}: {languages} languages, mean {mean:.2} captures")
+                })
+                .collect(),
+        ),
+
+        AnalysisQuery::UnknownThemeKeys { count } => {
+            let used_captures = analysis::used_captures(&captures_by_language);
+
+            QueryResult::list_or_count(
+                analysis::unknown_theme_keys(&supported_captures_by_theme, &used_captures)
+                    .into_iter()
+                    .map(|(theme, keys)| format!("{theme}: {}", keys.join(", ")))
+                    .collect(),
+                count,
+            )
         }
     };
+
+    display_query_result(&result, format);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zeta::types::{themes_v1_schema, themes_v2_schema, ThemeExtension};
+
+    const THEME_FAMILY: &str = r#"{
+        "name": "Test",
+        "author": "Zeta",
+        "themes": [{ "name": "Test Dark", "appearance": "dark", "style": { "syntax": {} } }]
+    }"#;
+
+    fn extension(id: &str, manifest: &str, r#type: ExtensionType) -> Extension {
+        Extension {
+            id: id.to_string(),
+            metadata: ExtensionMetadata::TomlManifest(
+                toml::from_str(manifest).expect("test manifest should parse"),
+            ),
+            builtin: false,
+            git_provider: None,
+            git_url: None,
+            commit: None,
+            r#type,
+        }
+    }
+
+    fn theme_extension(id: &str, themes: Vec<Option<Theme>>) -> Extension {
+        extension(
+            id,
+            r#"
+                name = "Test"
+                version = "0.1.0"
+                authors = ["Zeta"]
+                repository = "https://github.com/zeta/test"
+            "#,
+            ExtensionType::Theme(ThemeExtension { themes }),
+        )
+    }
+
+    #[test]
+    fn theme_schema_filter_keeps_extensions_with_matching_themes() {
+        let extension = theme_extension(
+            "mixed",
+            vec![
+                Some(Theme::V1(Some(
+                    serde_json_lenient::from_str::<themes_v1_schema::ThemeFamilyContent>(
+                        THEME_FAMILY,
+                    )
+                    .unwrap(),
+                ))),
+                Some(Theme::V2(Some(
+                    serde_json_lenient::from_str::<themes_v2_schema::ThemeFamilyContent>(
+                        THEME_FAMILY,
+                    )
+                    .unwrap(),
+                ))),
+            ],
+        );
+
+        for (schema, expected) in [
+            (BasicThemeSchema::V1, true),
+            (BasicThemeSchema::V2, true),
+            (BasicThemeSchema::Other, false),
+        ] {
+            let filters = FindFilters {
+                theme_schema: Some(schema),
+                ..Default::default()
+            };
+            assert_eq!(filters.matches(&extension), expected);
+        }
+    }
+
+    #[test]
+    fn find_extension_fails_on_unknown_id() {
+        let extensions = [theme_extension("catppuccin", Vec::new())];
+
+        assert!(find_extension(&extensions, "catppuccin").is_ok());
+        let error = find_extension(&extensions, "catpuccin").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "no extension with id 'catpuccin' (did you mean 'catppuccin'?)"
+        );
+        assert!(find_extension(&[], "catppuccin").is_err());
+    }
+
+    fn manifest_by(author: &str) -> String {
+        format!(
+            r#"
+                name = "Test"
+                version = "0.1.0"
+                authors = ["{author}"]
+                repository = "https://github.com/zeta/test"
+            "#
+        )
+    }
+
+    #[test]
+    fn author_filter_matches_any_author_case_insensitively() {
+        let alice = extension(
+            "alice-theme",
+            &manifest_by("Alice Liddell <alice@example.com>"),
+            ExtensionType::Theme(ThemeExtension::default()),
+        );
+        let bob = extension(
+            "bob-theme",
+            &manifest_by("Bob"),
+            ExtensionType::Theme(ThemeExtension::default()),
+        );
+
+        let matching_ids = |author: &[&str]| -> Vec<String> {
+            let filters = FindFilters {
+                author: author.iter().map(ToString::to_string).collect(),
+                ..Default::default()
+            };
+            [&alice, &bob]
+                .into_iter()
+                .filter(|extension| filters.matches(extension))
+                .map(|extension| extension.id.clone())
+                .collect()
+        };
+
+        assert_eq!(matching_ids(&["alice"]), ["alice-theme"]);
+        assert_eq!(matching_ids(&["BOB"]), ["bob-theme"]);
+        assert_eq!(
+            matching_ids(&["alice", "bob"]),
+            ["alice-theme", "bob-theme"]
+        );
+        assert!(matching_ids(&["carol"]).is_empty());
+    }
+
+    #[test]
+    fn sort_truncate_hashmap_is_deterministic() {
+        let entries = [("b", 2), ("a", 2), ("d", 1), ("c", 2), ("e", 3), ("f", 1)];
+        // Each map gets its own random hasher state, so iteration orders differ.
+        let forward: HashMap<String, usize> = entries
+            .iter()
+            .map(|(key, count)| ((*key).to_string(), *count))
+            .collect();
+        let backward: HashMap<String, usize> = entries
+            .iter()
+            .rev()
+            .map(|(key, count)| ((*key).to_string(), *count))
+            .collect();
+
+        for sort in [SortKey::Count, SortKey::Name] {
+            for order in [SortOrder::Asc, SortOrder::Desc] {
+                let options = RankingOptions {
+                    order,
+                    limit: 4,
+                    min_count: 0,
+                    with_total: false,
+                    sort: sort.clone(),
+                };
+                assert_eq!(
+                    format!("{:?}", sort_truncate_hashmap(&forward, &options)),
+                    format!("{:?}", sort_truncate_hashmap(&backward, &options)),
+                );
+            }
+        }
+
+        let options = RankingOptions {
+            order: SortOrder::Desc,
+            limit: 3,
+            min_count: 0,
+            with_total: false,
+            sort: SortKey::Count,
+        };
+        let ranking: Vec<_> = sort_truncate_hashmap(&forward, &options)
+            .into_iter()
+            .map(|(key, count)| (key.as_str(), *count))
+            .collect();
+        assert_eq!(ranking, [("e", 3), ("a", 2), ("b", 2)]);
+    }
+
+    #[test]
+    fn duplicate_captures_fails_on_unknown_or_non_language_id() {
+        let extensions = [theme_extension("catppuccin", Vec::new())];
+        let duplicate_captures = |language: &str| {
+            handle_query(
+                AnalysisQuery::DuplicateCaptures {
+                    language: language.to_string(),
+                    kind: QueryKind::Highlights,
+                },
+                false,
+                &extensions,
+                &OutputFormat::Text,
+            )
+        };
+
+        assert!(duplicate_captures("rust").is_err());
+        let error = duplicate_captures("catppuccin").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "extension 'catppuccin' is a theme extension, not a language extension"
+        );
+    }
+
+    #[test]
+    fn scan_cache_file_name_is_stable() {
+        assert_eq!(
+            scan_cache_file_name(scan::DEFAULT_EXTENSIONS_REPOSITORY),
+            "extensions-scan-dump.json"
+        );
+        // Pinned so a toolchain update can't silently orphan existing caches.
+        assert_eq!(
+            scan_cache_file_name("https://gitlab.com/zed-industries/extensions.git"),
+            "extensions-scan-dump-291edf95a5ac96b0.json"
+        );
+    }
+
+    fn fixture_extensions() -> Vec<Extension> {
+        let (scan_dump, _) = scan::local_extensions(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/extensions"),
+        )
+        .unwrap();
+        scan_dump.extensions
+    }
+
+    /// Sorted ids of the `extensions` matching `filters`.
+    fn matching_ids(filters: &FindFilters, extensions: &[Extension]) -> Vec<String> {
+        let mut ids: Vec<String> = extensions
+            .iter()
+            .filter(|extension| filters.matches(extension))
+            .map(|extension| extension.id.clone())
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn grammar_filter_matches_any_declared_grammar() {
+        let extensions = fixture_extensions();
+        let grammar = |grammar: &str| FindFilters {
+            grammar: Some(grammar.to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            matching_ids(&grammar("alpha"), &extensions),
+            ["test-grammars"]
+        );
+        assert_eq!(
+            matching_ids(&grammar("beta"), &extensions),
+            ["test-grammars"]
+        );
+        assert_eq!(
+            matching_ids(&grammar("test"), &extensions),
+            ["test-language"]
+        );
+        assert!(matching_ids(&grammar("gamma"), &extensions).is_empty());
+    }
+
+    #[test]
+    fn builtin_captures_are_excluded_from_counts() {
+        let test_language = || {
+            fixture_extensions()
+                .into_iter()
+                .find(|extension| extension.id == "test-language")
+                .unwrap()
+        };
+        let capture_counts = |exclude: bool| {
+            let builtin = Extension {
+                id: "zed-test".to_string(),
+                builtin: true,
+                ..test_language()
+            };
+            let extensions = without_builtin(vec![test_language(), builtin], exclude);
+            let capture_tables = CaptureTables::new(&extensions, &QueryKind::Highlights, false);
+            analysis::count_capture_usage(&capture_tables.captures_by_language)
+        };
+
+        assert_eq!(capture_counts(false)["comment"], 2);
+        assert_eq!(capture_counts(true)["comment"], 1);
+    }
+
+    #[test]
+    fn theme_count_filters_only_count_valid_themes() {
+        let valid_theme = || {
+            Some(Theme::V2(Some(
+                serde_json_lenient::from_str::<themes_v2_schema::ThemeFamilyContent>(THEME_FAMILY)
+                    .unwrap(),
+            )))
+        };
+        let extensions = [
+            theme_extension("none", Vec::new()),
+            theme_extension(
+                "one",
+                vec![
+                    valid_theme(),
+                    Some(Theme::Invalid(PathBuf::from("broken.json"))),
+                ],
+            ),
+            theme_extension("three", vec![valid_theme(), valid_theme(), valid_theme()]),
+        ];
+        let themes = |min_themes: Option<usize>, max_themes: Option<usize>| FindFilters {
+            min_themes,
+            max_themes,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            matching_ids(&themes(Some(1), None), &extensions),
+            ["one", "three"]
+        );
+        assert_eq!(
+            matching_ids(&themes(None, Some(1)), &extensions),
+            ["none", "one"]
+        );
+        assert_eq!(
+            matching_ids(&themes(Some(2), Some(3)), &extensions),
+            ["three"]
+        );
+        assert!(matching_ids(&themes(Some(4), None), &extensions).is_empty());
+    }
+
+    #[test]
+    fn parse_capture_strips_leading_at() {
+        assert_eq!(parse_capture("@function").unwrap(), "function");
+        assert_eq!(parse_capture("function").unwrap(), "function");
+        assert_eq!(
+            parse_capture("@function.method").unwrap(),
+            "function.method"
+        );
+        assert!(parse_capture("@").is_err());
+    }
+
+    #[test]
+    fn capture_matcher_ignores_case_on_request() {
+        let exact = capture_matcher("Function", false, false).unwrap();
+        assert!(exact("Function"));
+        assert!(!exact("function"));
+
+        let ignore_case = capture_matcher("Function", false, true).unwrap();
+        assert!(ignore_case("function"));
+        assert!(ignore_case("FUNCTION"));
+        assert!(!ignore_case("function.method"));
+
+        let glob = capture_matcher("Function.*", true, true).unwrap();
+        assert!(glob("function.method"));
+        assert!(!glob("keyword"));
+    }
+
+    #[test]
+    fn language_name_filter_matches_every_extension_defining_it() {
+        let extensions = fixture_extensions();
+        let language_name = |language_name: &str| FindFilters {
+            language_name: Some(language_name.to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            matching_ids(&language_name("test"), &extensions),
+            ["test-language", "test-language-fork"]
+        );
+        assert_eq!(
+            matching_ids(&language_name("Alpha"), &extensions),
+            ["test-grammars"]
+        );
+        assert!(matching_ids(&language_name("Gamma"), &extensions).is_empty());
+    }
+
+    #[test]
+    fn language_server_filters_match_declared_servers() {
+        let extensions = fixture_extensions();
+
+        let has_language_server = FindFilters {
+            has_language_server: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            matching_ids(&has_language_server, &extensions),
+            ["test-grammars"]
+        );
+
+        let language_server = |language_server: &str| FindFilters {
+            language_server: Some(language_server.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            matching_ids(&language_server("alpha-lsp"), &extensions),
+            ["test-grammars"]
+        );
+        assert!(matching_ids(&language_server("beta-lsp"), &extensions).is_empty());
+    }
+
+    #[test]
+    fn indentation_filters_match_a_single_language() {
+        let extensions = fixture_extensions();
+        let indentation = |tab_size: Option<usize>, hard_tabs: Option<bool>| FindFilters {
+            tab_size,
+            hard_tabs,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            matching_ids(&indentation(Some(2), None), &extensions),
+            ["test-grammars"]
+        );
+        assert_eq!(
+            matching_ids(&indentation(Some(4), None), &extensions),
+            ["test-language"]
+        );
+        assert_eq!(
+            matching_ids(&indentation(None, Some(true)), &extensions),
+            ["test-grammars"]
+        );
+        assert_eq!(
+            matching_ids(&indentation(Some(8), Some(true)), &extensions),
+            ["test-grammars"]
+        );
+        // Both settings have to hold for the same language.
+        assert!(matching_ids(&indentation(Some(2), Some(true)), &extensions).is_empty());
+    }
+
+    #[test]
+    fn aborted_scan_dump_write_keeps_previous_dump() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let path = cache_dir.path().join("extensions-scan-dump.json");
+        let scan_dump = |head: &str| ScanDump {
+            head: Some(head.to_string()),
+            extensions: Vec::new(),
+            failed: Vec::new(),
+        };
+
+        write_scan_dump(&path, &scan_dump("old")).unwrap();
+
+        // A run killed mid-write leaves a truncated temporary file behind.
+        let contents = fs::read_to_string(&path).unwrap();
+        let temporary_path = cache_dir.path().join(format!(
+            "extensions-scan-dump.json.{}.tmp",
+            std::process::id()
+        ));
+        fs::write(&temporary_path, &contents[..contents.len() / 2]).unwrap();
+
+        assert_eq!(read_scan_dump(&path).unwrap().head.as_deref(), Some("old"));
+
+        // Writing fails if the temporary file can't be created.
+        fs::remove_file(&temporary_path).unwrap();
+        fs::create_dir(&temporary_path).unwrap();
+        assert!(write_scan_dump(&path, &scan_dump("new")).is_err());
+        assert_eq!(read_scan_dump(&path).unwrap().head.as_deref(), Some("old"));
+        fs::remove_dir(&temporary_path).unwrap();
+
+        // Replacing a directory fails after the temporary file was written, which is removed again.
+        let directory_path = cache_dir.path().join("directory");
+        fs::create_dir_all(directory_path.join("entry")).unwrap();
+        let error = write_scan_dump(&directory_path, &scan_dump("new")).unwrap_err();
+        assert!(error.to_string().starts_with("failed to replace"));
+        assert!(!cache_dir
+            .path()
+            .join(format!("directory.{}.tmp", std::process::id()))
+            .exists());
+        assert_eq!(read_scan_dump(&path).unwrap().head.as_deref(), Some("old"));
+
+        write_scan_dump(&path, &scan_dump("new")).unwrap();
+        assert_eq!(read_scan_dump(&path).unwrap().head.as_deref(), Some("new"));
+        assert!(!temporary_path.exists());
+    }
+
+    #[test]
+    fn capture_glob_with_trailing_wildcard_matches_stem() {
+        let matches = capture_matcher("function.*", true, false).unwrap();
+        assert!(matches("function"));
+        assert!(matches("function.method"));
+        assert!(matches("function.method.call"));
+        assert!(!matches("functional"));
+        assert!(!matches("keyword.function"));
+
+        let matches = capture_matcher("*.builtin", true, false).unwrap();
+        assert!(matches("type.builtin"));
+        assert!(!matches("builtin"));
+    }
 }
@@ -1,115 +1,583 @@
-use std::{fs, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
-use git2::Repository;
+use anyhow::{Context, Result};
+use git2::{build::RepoBuilder, FetchOptions, Repository, ResetType, SubmoduleUpdateOptions};
+use indicatif::{ProgressBar, ProgressStyle};
 use log::debug;
+use rayon::prelude::*;
 use url::Url;
 
-use crate::types::{
-    Extension, ExtensionMetadata, ExtensionType, ExtensionsMetadata, JsonManifest,
-    LanguageExtension, ThemeExtension, TomlManifest,
+use crate::{
+    analysis,
+    types::{
+        Extension, ExtensionMetadata, ExtensionType, ExtensionsMetadata, ExtensionsMetadataEntry,
+        JsonManifest, LanguageExtension, ScanDump, ThemeExtension, TomlManifest,
+    },
 };
 
-pub fn clone_extensions_repository(dir: &PathBuf, url: &str) -> Result<Repository> {
+/// Problem that caused an extension to fail to scan, attached as context to its scan error so it
+/// can be recovered with [`anyhow::Error::downcast_ref`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ScanProblem {
+    Submodule,
+    MissingManifest,
+    InvalidManifest,
+    UnknownType,
+}
+
+impl fmt::Display for ScanProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScanProblem::Submodule => "failed to update submodule",
+            ScanProblem::MissingManifest => "missing manifest",
+            ScanProblem::InvalidManifest => "invalid manifest",
+            ScanProblem::UnknownType => "unknown extension type",
+        })
+    }
+}
+
+/// Upstream repository tracking all extensions as submodules.
+pub const DEFAULT_EXTENSIONS_REPOSITORY: &str = "https://github.com/zed-industries/extensions.git";
+
+/// Stable 64-bit FNV-1a hash of a repository URL, as hex. Unlike `DefaultHasher`, the output never
+/// changes between Rust releases, so it can key files that outlive a toolchain upgrade.
+pub fn repository_hash(url: &str) -> String {
+    let hash = url.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+/// Directory (relative to the cache directory) to clone an extensions repository into. The default
+/// repository keeps its original `zed-industries/extensions` directory, while any other repository
+/// is keyed by [`repository_hash`], so forks and mirrors never open each other's clone.
+fn repository_dir(url: &str) -> PathBuf {
+    if url == DEFAULT_EXTENSIONS_REPOSITORY {
+        PathBuf::from("zed-industries/extensions")
+    } else {
+        PathBuf::from(format!("extensions-{}", repository_hash(url)))
+    }
+}
+
+/// Default limit for a single git network operation.
+pub const DEFAULT_GIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Set the libgit2 connect and read timeouts, so a remote that stops responding makes the operation
+/// fail instead of hanging. A zero timeout leaves both at the system defaults, i.e. no limit.
+///
+/// # Safety
+///
+/// The timeouts are global libgit2 options that aren't synchronized, so this must not be called
+/// while any other thread may be running git operations (e.g. only at startup, before any
+/// repository is opened).
+pub unsafe fn set_git_timeout(timeout: Duration) -> Result<()> {
+    let millis = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+    // SAFETY: upheld by the caller, see above.
+    unsafe {
+        git2::opts::set_server_connect_timeout_in_milliseconds(millis)?;
+        git2::opts::set_server_timeout_in_milliseconds(millis)?;
+    }
+    Ok(())
+}
+
+/// Fetch options for cloning and updating submodules, limiting history to the latest commit when
+/// `shallow` is set. Stalled transfers are handled by the timeouts from [`set_git_timeout`].
+fn fetch_options<'a>(shallow: bool) -> FetchOptions<'a> {
+    let mut fetch_options = FetchOptions::new();
+    if shallow {
+        fetch_options.depth(1);
+    }
+    fetch_options
+}
+
+pub fn clone_extensions_repository(dir: &Path, url: &str, shallow: bool) -> Result<Repository> {
     let repository = match Repository::open(dir) {
         Ok(repo) => repo,
-        Err(_) => Repository::clone(url, dir)?,
+        Err(_) => RepoBuilder::new()
+            .fetch_options(fetch_options(shallow))
+            .clone(url, dir)?,
     };
     debug!("opened {url} repository in {dir:?}");
 
     Ok(repository)
 }
 
-pub fn extensions(cache_dir: &PathBuf) -> Result<Vec<Extension>> {
-    let extensions_dir = cache_dir.join("zed-industries/extensions");
-    let extensions_repository = clone_extensions_repository(
-        &extensions_dir,
-        "https://github.com/zed-industries/extensions.git",
-    )?;
+/// Fetch the already-cloned extensions repository and reset it to the remote HEAD, returning the
+/// new HEAD commit SHA, or `None` if the repository hasn't been cloned yet.
+pub fn update_extensions_repository(
+    cache_dir: &Path,
+    repository_url: &str,
+) -> Result<Option<String>> {
+    let Ok(repository) = Repository::open(cache_dir.join(repository_dir(repository_url))) else {
+        return Ok(None);
+    };
+
+    repository
+        .find_remote("origin")?
+        .fetch(&["HEAD"], Some(&mut fetch_options(false)), None)
+        .with_context(|| format!("failed to fetch {repository_url}"))?;
+    let fetch_head = repository.find_reference("FETCH_HEAD")?.peel_to_commit()?;
+    repository.reset(fetch_head.as_object(), ResetType::Hard, None)?;
+    debug!("updated {repository_url} repository to {}", fetch_head.id());
+
+    Ok(Some(fetch_head.id().to_string()))
+}
+
+/// Scan all extensions, returning the successfully scanned extensions (and the repository HEAD they
+/// were scanned at) alongside the ids and errors of any extensions that failed to scan.
+///
+/// Extensions from a `previous` scan whose submodule is still at the same commit are reused as-is
+/// instead of being re-parsed; pass an empty `Vec` to force a full scan.
+pub fn extensions(
+    cache_dir: &Path,
+    repository_url: &str,
+    shallow: bool,
+    previous: Vec<Extension>,
+) -> Result<(ScanDump, Vec<(String, anyhow::Error)>)> {
+    let extensions_dir = cache_dir.join(repository_dir(repository_url));
+    let extensions_repository =
+        clone_extensions_repository(&extensions_dir, repository_url, shallow)?;
+
+    let head = extensions_repository
+        .head()
+        .ok()
+        .and_then(|head| head.target())
+        .map(|oid| oid.to_string());
 
-    let extensions_metadata: ExtensionsMetadata =
-        toml::from_str(&fs::read_to_string(extensions_dir.join("extensions.toml"))?)?;
+    let extensions_metadata = read_extensions_metadata(&extensions_dir)?;
 
+    let mut previous: HashMap<String, Extension> = previous
+        .into_iter()
+        .map(|extension| (extension.id.clone(), extension))
+        .collect();
+
+    // `git2` repositories and submodules aren't `Sync`, so submodules are updated sequentially
+    // and only the extension files are read and parsed in parallel.
+    let started = Instant::now();
+    let mut submodules: Vec<Submodule> = Vec::new();
     let mut extensions: Vec<Extension> = Vec::new();
+    let mut errors: Vec<(String, anyhow::Error)> = Vec::new();
+
+    // Only show progress when stderr is a terminal so piped output stays clean.
+    let progress = if io::stderr().is_terminal() {
+        ProgressBar::new(extensions_metadata.0.len() as u64).with_style(
+            ProgressStyle::with_template("{bar:40} {pos}/{len} {msg}")
+                .expect("progress bar template should be valid"),
+        )
+    } else {
+        ProgressBar::hidden()
+    };
 
     for (id, extension) in &extensions_metadata.0 {
-        let mut submodule = extensions_repository
-            .find_submodule(&extension.submodule)
-            .expect("submodule for extension should exist");
-        submodule.update(true, None)?;
+        progress.set_message(id.clone());
+        let (extension_path, builtin, url, commit) =
+            match update_submodule(&extensions_repository, &extensions_dir, extension) {
+                Ok(submodule) => submodule,
+                Err(error) => {
+                    errors.push((id.clone(), error.context(ScanProblem::Submodule)));
+                    progress.inc(1);
+                    continue;
+                }
+            };
         debug!("cloned extension submodule '{}'", &id);
-        let extension_path = extensions_dir
-            .join(&extension.submodule)
-            .join(extension.path.clone().unwrap_or(String::new()));
-
-        let builtin = extension.submodule == "extensions/zed";
-        let url = Url::parse(
-            submodule
-                .url()
-                .expect("extension submodule should have valid url"),
-        )?;
-
-        let metadata: ExtensionMetadata = match (
-            extension_path.join("extension.toml"),
-            extension_path.join("extension.json"),
-        ) {
-            (toml_path, _) if toml_path.exists() => ExtensionMetadata::TomlManifest(
-                toml::from_str::<TomlManifest>(&fs::read_to_string(toml_path)?)?,
-            ),
-            (_, json_path) if json_path.exists() => ExtensionMetadata::JsonManifest(
-                serde_json_lenient::from_str::<JsonManifest>(&fs::read_to_string(json_path)?)?,
-            ),
-            _ => panic!("Extension manifest not found"),
-        };
 
-        let r#type = match (
-            extension_path.join("languages"),
-            extension_path.join("themes"),
-        ) {
-            (lang_path, _) if lang_path.exists() => {
-                ExtensionType::Language(LanguageExtension::from_scan(&lang_path)?)
-            }
-            (_, theme_path) if theme_path.exists() => {
-                ExtensionType::Theme(ThemeExtension::from_scan(&theme_path)?)
+        progress.inc(1);
+        match previous.remove(id.as_str()) {
+            Some(mut extension) if commit.is_some() && extension.commit == commit => {
+                debug!("extension '{id}' unchanged since last scan");
+                // Extensions cached before captures were stored get them on their first rescan.
+                if let ExtensionType::Language(language_extension) = &mut extension.r#type {
+                    analysis::store_query_captures(language_extension);
+                }
+                extensions.push(extension);
             }
-            _ => match &metadata {
-                ExtensionMetadata::TomlManifest(manifest) => {
-                    if manifest.grammars.is_some() || manifest.language_servers.is_some() {
-                        ExtensionType::Language(LanguageExtension::default())
-                    } else if manifest.slash_commands.is_some() {
-                        ExtensionType::SlashCommand
-                    } else if manifest.context_servers.is_some() {
-                        ExtensionType::ContextServer
-                    } else {
-                        anyhow::bail!(
-                            "Unknown extension type for extension '{}' with TOML manifest",
-                            id
-                        );
-                    }
+            _ => submodules.push((id, extension_path, builtin, Some(url), commit)),
+        }
+    }
+    progress.finish_and_clear();
+    debug!(
+        "updated {} extension submodules in {:?} ({} unchanged)",
+        submodules.len() + extensions.len(),
+        started.elapsed(),
+        extensions.len()
+    );
+
+    scan_submodules(&submodules, &mut extensions, &mut errors);
+
+    Ok((
+        ScanDump {
+            head,
+            extensions,
+            failed: failed_ids(&errors),
+        },
+        errors,
+    ))
+}
+
+/// Scan all extensions in an already checked-out extensions directory (containing
+/// `extensions.toml` and the extension submodules) without touching Git. Extensions scanned this
+/// way have no Git URL or commit.
+pub fn local_extensions(extensions_dir: &Path) -> Result<(ScanDump, Vec<(String, anyhow::Error)>)> {
+    let extensions_metadata = read_extensions_metadata(extensions_dir)?;
+
+    let submodules: Vec<Submodule> = extensions_metadata
+        .0
+        .iter()
+        .map(|(id, extension)| {
+            (
+                id,
+                extension_path(extensions_dir, extension),
+                extension.submodule == "extensions/zed",
+                None,
+                None,
+            )
+        })
+        .collect();
+
+    let mut extensions: Vec<Extension> = Vec::new();
+    let mut errors: Vec<(String, anyhow::Error)> = Vec::new();
+    scan_submodules(&submodules, &mut extensions, &mut errors);
+
+    Ok((
+        ScanDump {
+            head: None,
+            extensions,
+            failed: failed_ids(&errors),
+        },
+        errors,
+    ))
+}
+
+/// Sorted ids of the extensions that failed to scan.
+fn failed_ids(errors: &[(String, anyhow::Error)]) -> Vec<String> {
+    let mut failed: Vec<String> = errors.iter().map(|(id, _)| id.clone()).collect();
+    failed.sort_unstable();
+    failed
+}
+
+/// Extension submodule to scan, as its id, extension path, whether it's built in, submodule URL,
+/// and commit.
+type Submodule<'a> = (&'a String, PathBuf, bool, Option<Url>, Option<String>);
+
+fn read_extensions_metadata(extensions_dir: &Path) -> Result<ExtensionsMetadata> {
+    let extensions_metadata_path = extensions_dir.join("extensions.toml");
+    toml::from_str(&fs::read_to_string(&extensions_metadata_path)?)
+        .with_context(|| format!("parsing {extensions_metadata_path:?}"))
+}
+
+fn extension_path(extensions_dir: &Path, extension: &ExtensionsMetadataEntry) -> PathBuf {
+    extensions_dir
+        .join(&extension.submodule)
+        .join(extension.path.clone().unwrap_or_default())
+}
+
+/// Read and parse the files of each submodule in parallel.
+fn scan_submodules(
+    submodules: &[Submodule],
+    extensions: &mut Vec<Extension>,
+    errors: &mut Vec<(String, anyhow::Error)>,
+) {
+    let started = Instant::now();
+    let results: Vec<Result<Extension>> = submodules
+        .par_iter()
+        .map(|(id, extension_path, builtin, url, commit)| {
+            scan_extension(id, extension_path, *builtin, url.as_ref(), commit.clone())
+        })
+        .collect();
+
+    for ((id, ..), result) in submodules.iter().zip(results) {
+        match result {
+            Ok(extension) => extensions.push(extension),
+            Err(error) => errors.push(((*id).clone(), error)),
+        }
+    }
+    debug!(
+        "scanned {} extensions ({} failed) in {:?}",
+        extensions.len(),
+        errors.len(),
+        started.elapsed()
+    );
+}
+
+/// Update the submodule of an extension, returning the extension's path, whether it's built in, its
+/// submodule URL, and the commit it's checked out at.
+fn update_submodule(
+    repository: &Repository,
+    extensions_dir: &Path,
+    extension: &ExtensionsMetadataEntry,
+) -> Result<(PathBuf, bool, Url, Option<String>)> {
+    let mut submodule = repository
+        .find_submodule(&extension.submodule)
+        .with_context(|| {
+            format!(
+                "extensions.toml references submodule '{}', which isn't registered in .gitmodules",
+                extension.submodule
+            )
+        })?;
+    // Submodules are never fetched shallowly: a depth-limited fetch only has the tips of the
+    // submodule's branches, which often don't include the commit pinned by the superproject.
+    submodule
+        .update(
+            true,
+            Some(SubmoduleUpdateOptions::new().fetch(fetch_options(false))),
+        )
+        .with_context(|| format!("failed to update submodule '{}'", extension.submodule))?;
+
+    let extension_path = extension_path(extensions_dir, extension);
+    let builtin = extension.submodule == "extensions/zed";
+    let url = submodule
+        .url()
+        .with_context(|| format!("submodule '{}' has no valid URL", extension.submodule))?;
+    let url = Url::parse(url).with_context(|| format!("invalid submodule URL '{url}'"))?;
+    let commit = submodule.head_id().map(|oid| oid.to_string());
+
+    Ok((extension_path, builtin, url, commit))
+}
+
+fn scan_extension(
+    id: &str,
+    extension_path: &Path,
+    builtin: bool,
+    url: Option<&Url>,
+    commit: Option<String>,
+) -> Result<Extension> {
+    let metadata: ExtensionMetadata = match (
+        extension_path.join("extension.toml"),
+        extension_path.join("extension.json"),
+        ["extension.yaml", "extension.yml"]
+            .iter()
+            .map(|name| extension_path.join(name))
+            .find(|path| path.exists()),
+    ) {
+        (toml_path, _, _) if toml_path.exists() => ExtensionMetadata::TomlManifest(
+            toml::from_str::<TomlManifest>(&fs::read_to_string(&toml_path)?)
+                .with_context(|| format!("parsing manifest for '{id}' at {toml_path:?}"))
+                .context(ScanProblem::InvalidManifest)?,
+        ),
+        (_, json_path, _) if json_path.exists() => ExtensionMetadata::JsonManifest(
+            serde_json_lenient::from_str::<JsonManifest>(&fs::read_to_string(&json_path)?)
+                .with_context(|| format!("parsing manifest for '{id}' at {json_path:?}"))
+                .context(ScanProblem::InvalidManifest)?,
+        ),
+        (_, _, Some(yaml_path)) => ExtensionMetadata::YamlManifest(
+            serde_norway::from_str::<TomlManifest>(&fs::read_to_string(&yaml_path)?)
+                .with_context(|| format!("parsing manifest for '{id}' at {yaml_path:?}"))
+                .context(ScanProblem::InvalidManifest)?,
+        ),
+        _ => {
+            return Err(
+                anyhow::anyhow!("extension '{id}' has no manifest at {extension_path:?}")
+                    .context(ScanProblem::MissingManifest),
+            )
+        }
+    };
+
+    let r#type = match (
+        extension_path.join("languages"),
+        extension_path.join("themes"),
+        extension_path.join("grammars"),
+    ) {
+        (lang_path, _, _) if lang_path.exists() => {
+            let mut language_extension = LanguageExtension::from_scan(&lang_path)?;
+            analysis::store_query_captures(&mut language_extension);
+            ExtensionType::Language(language_extension)
+        }
+        (_, theme_path, _) if theme_path.exists() => {
+            ExtensionType::Theme(ThemeExtension::from_scan(&theme_path)?)
+        }
+        (_, _, grammars_path) if grammars_path.exists() => {
+            ExtensionType::Language(LanguageExtension::from_grammars_scan(&grammars_path)?)
+        }
+        _ => match &metadata {
+            ExtensionMetadata::TomlManifest(manifest)
+            | ExtensionMetadata::YamlManifest(manifest) => {
+                if manifest.grammars.is_some() || manifest.language_servers.is_some() {
+                    ExtensionType::Language(LanguageExtension::default())
+                } else if manifest.slash_commands.is_some() {
+                    ExtensionType::SlashCommand
+                } else if manifest.context_servers.is_some() {
+                    ExtensionType::ContextServer
+                } else {
+                    return Err(anyhow::anyhow!(
+                        "Unknown extension type for extension '{}' with TOML or YAML manifest",
+                        id
+                    )
+                    .context(ScanProblem::UnknownType));
                 }
-                ExtensionMetadata::JsonManifest(manifest) => {
-                    if manifest.grammars.is_some() || manifest.languages.is_some() {
-                        ExtensionType::Language(LanguageExtension::default())
-                    } else if manifest.themes.is_some() {
-                        ExtensionType::Theme(ThemeExtension::default())
-                    } else {
-                        anyhow::bail!(
-                            "Unknown extension type for extension '{}' with JSON manifest",
-                            id
-                        );
-                    }
+            }
+            ExtensionMetadata::JsonManifest(manifest) => {
+                if manifest.grammars.is_some() || manifest.languages.is_some() {
+                    ExtensionType::Language(LanguageExtension::default())
+                } else if manifest.themes.is_some() {
+                    ExtensionType::Theme(ThemeExtension::default())
+                } else {
+                    return Err(anyhow::anyhow!(
+                        "Unknown extension type for extension '{}' with JSON manifest",
+                        id
+                    )
+                    .context(ScanProblem::UnknownType));
                 }
-            },
+            }
+        },
+    };
+
+    Ok(Extension {
+        id: id.to_string(),
+        metadata,
+        builtin,
+        git_provider: url.and_then(Url::host_str).map(ToString::to_string),
+        git_url: url.map(ToString::to_string),
+        commit,
+        r#type,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/extensions")
+    }
+
+    #[test]
+    fn scan_reports_extension_without_manifest() {
+        let (scan_dump, errors) = local_extensions(&fixture_dir()).unwrap();
+
+        assert!(scan_dump
+            .extensions
+            .iter()
+            .any(|extension| extension.id == "test-language"));
+        assert!(!scan_dump
+            .extensions
+            .iter()
+            .any(|extension| extension.id == "no-manifest"));
+
+        let (_, error) = errors
+            .iter()
+            .find(|(id, _)| id == "no-manifest")
+            .expect("extension without a manifest should fail to scan");
+        assert_eq!(
+            error.downcast_ref::<ScanProblem>(),
+            Some(&ScanProblem::MissingManifest)
+        );
+        assert!(scan_dump.failed.contains(&"no-manifest".to_string()));
+    }
+
+    #[test]
+    fn repository_dir_is_unique_per_url() {
+        let default = repository_dir(DEFAULT_EXTENSIONS_REPOSITORY);
+        let mirror = repository_dir("https://gitlab.com/zed-industries/extensions.git");
+        let scp = repository_dir("git@github.com:me/extensions.git");
+
+        assert_eq!(default, PathBuf::from("zed-industries/extensions"));
+        assert_ne!(mirror, default);
+        assert_ne!(scp, default);
+        assert_ne!(scp, mirror);
+        assert_ne!(scp, PathBuf::new());
+    }
+
+    #[test]
+    fn scan_reads_languages_from_grammars_dir() {
+        let (scan_dump, _) = local_extensions(&fixture_dir()).unwrap();
+
+        let extension = scan_dump
+            .extensions
+            .iter()
+            .find(|extension| extension.id == "test-assembly")
+            .expect("extension with a grammars directory should scan");
+        let ExtensionType::Language(language_extension) = &extension.r#type else {
+            panic!("expected a language extension, got {extension}");
         };
+        let [language] = language_extension.languages.as_slice() else {
+            panic!("expected a single language");
+        };
+        assert_eq!(language.config.name, "asm");
+        assert_eq!(language.config.grammar, "asm");
+    }
+
+    #[test]
+    fn scan_reads_yaml_manifest() {
+        let (scan_dump, _) = local_extensions(&fixture_dir()).unwrap();
 
-        extensions.push(Extension {
-            id: id.clone(),
-            metadata,
-            builtin,
-            git_provider: Some(url.host_str().unwrap().to_string()),
-            r#type,
-        });
+        let extension = scan_dump
+            .extensions
+            .iter()
+            .find(|extension| extension.id == "test-yaml")
+            .expect("extension with a YAML manifest should scan");
+        let ExtensionMetadata::YamlManifest(manifest) = &extension.metadata else {
+            panic!("expected a YAML manifest, got {extension}");
+        };
+        assert_eq!(manifest.name, "Test YAML");
+        assert_eq!(manifest.authors, ["Zeta"]);
+        assert!(matches!(extension.r#type, ExtensionType::Theme(_)));
     }
 
-    Ok(extensions)
+    #[test]
+    fn scan_reports_extension_without_registered_submodule() {
+        // A superproject whose extensions.toml lists an extension but has no .gitmodules entry.
+        let superproject_dir = tempfile::tempdir().unwrap();
+        let superproject = Repository::init(superproject_dir.path()).unwrap();
+        fs::write(
+            superproject_dir.path().join("extensions.toml"),
+            "[unregistered]\nsubmodule = \"extensions/unregistered\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        let mut index = superproject.index().unwrap();
+        index.add_path(Path::new("extensions.toml")).unwrap();
+        let tree = superproject.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Zeta", "zeta@example.com").unwrap();
+        superproject
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "Add extensions",
+                &tree,
+                &[],
+            )
+            .unwrap();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let url = Url::from_directory_path(superproject_dir.path()).unwrap();
+        let (scan_dump, errors) =
+            extensions(cache_dir.path(), url.as_str(), false, Vec::new()).unwrap();
+
+        assert!(scan_dump.extensions.is_empty());
+        let (_, error) = errors
+            .iter()
+            .find(|(id, _)| id == "unregistered")
+            .expect("extension without a registered submodule should fail to scan");
+        assert_eq!(
+            error.downcast_ref::<ScanProblem>(),
+            Some(&ScanProblem::Submodule)
+        );
+        assert_eq!(scan_dump.failed, ["unregistered"]);
+    }
+
+    #[test]
+    fn scan_skips_empty_language_dir() {
+        let (scan_dump, _) = local_extensions(&fixture_dir()).unwrap();
+
+        let extension = scan_dump
+            .extensions
+            .iter()
+            .find(|extension| extension.id == "test-language")
+            .expect("extension with an empty language directory should scan");
+        let ExtensionType::Language(language_extension) = &extension.r#type else {
+            panic!("expected a language extension, got {extension}");
+        };
+        let names: Vec<&str> = language_extension
+            .languages
+            .iter()
+            .map(|language| language.config.name.as_str())
+            .collect();
+        assert_eq!(names, ["Test"]);
+    }
 }
@@ -1,6 +1,7 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{collections::HashMap, fmt, fs, path::PathBuf};
 
 use anyhow::Result;
+use clap::ValueEnum;
 use log::warn;
 use serde::{Deserialize, Serialize};
 
@@ -12,6 +13,11 @@ pub mod themes_v2_schema {
     #![allow(clippy::all)]
     include!(concat!(env!("OUT_DIR"), "/themes-v2.rs"));
 }
+#[cfg(themes_v3)]
+pub mod themes_v3_schema {
+    #![allow(clippy::all)]
+    include!(concat!(env!("OUT_DIR"), "/themes-v3.rs"));
+}
 
 /// zed-industries/extensions's `extensions.toml` file structure for tracking extensions as submodules.
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,6 +31,18 @@ pub struct ExtensionsMetadataEntry {
     pub version: String,
 }
 
+/// Cached result of scanning an extensions repository.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ScanDump {
+    /// Commit SHA the extensions repository was at when scanned.
+    pub head: Option<String>,
+    pub extensions: Vec<Extension>,
+    /// Ids of the extensions that failed to scan. Missing from scans cached before failures were
+    /// recorded.
+    #[serde(default)]
+    pub failed: Vec<String>,
+}
+
 /// Base structure for an extension.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Extension {
@@ -32,12 +50,21 @@ pub struct Extension {
     pub metadata: ExtensionMetadata,
     pub builtin: bool,
     pub git_provider: Option<String>,
+    pub git_url: Option<String>,
+    /// Commit SHA the extension submodule was scanned at.
+    pub commit: Option<String>,
     pub r#type: ExtensionType,
 }
 
-impl ToString for Extension {
-    fn to_string(&self) -> String {
-        self.id.clone()
+impl fmt::Display for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}) [{}]",
+            self.id,
+            self.metadata.name(),
+            self.r#type.tag()
+        )
     }
 }
 
@@ -49,11 +76,86 @@ pub enum ExtensionType {
     ContextServer,
 }
 
-/// `extension.toml` or `extension.json` file structure for an extension.
+impl ExtensionType {
+    /// Short, kebab-case tag for the extension type.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            ExtensionType::Theme(_) => "theme",
+            ExtensionType::Language(_) => "language",
+            ExtensionType::SlashCommand => "slash-command",
+            ExtensionType::ContextServer => "context-server",
+        }
+    }
+}
+
+/// `extension.toml`, `extension.json`, or `extension.yaml` file structure for an extension.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ExtensionMetadata {
     TomlManifest(TomlManifest),
     JsonManifest(JsonManifest),
+    /// YAML manifests share the structure of TOML manifests.
+    YamlManifest(TomlManifest),
+}
+
+impl ExtensionMetadata {
+    /// Short, lowercase tag for the manifest format.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            ExtensionMetadata::TomlManifest(_) => "toml",
+            ExtensionMetadata::JsonManifest(_) => "json",
+            ExtensionMetadata::YamlManifest(_) => "yaml",
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            ExtensionMetadata::TomlManifest(manifest)
+            | ExtensionMetadata::YamlManifest(manifest) => &manifest.name,
+            ExtensionMetadata::JsonManifest(manifest) => &manifest.name,
+        }
+    }
+
+    pub fn version(&self) -> &str {
+        match self {
+            ExtensionMetadata::TomlManifest(manifest)
+            | ExtensionMetadata::YamlManifest(manifest) => &manifest.version,
+            ExtensionMetadata::JsonManifest(manifest) => &manifest.version,
+        }
+    }
+
+    pub fn authors(&self) -> &[String] {
+        match self {
+            ExtensionMetadata::TomlManifest(manifest)
+            | ExtensionMetadata::YamlManifest(manifest) => &manifest.authors,
+            ExtensionMetadata::JsonManifest(manifest) => &manifest.authors,
+        }
+    }
+
+    pub fn repository(&self) -> &str {
+        match self {
+            ExtensionMetadata::TomlManifest(manifest)
+            | ExtensionMetadata::YamlManifest(manifest) => &manifest.repository,
+            ExtensionMetadata::JsonManifest(manifest) => &manifest.repository,
+        }
+    }
+
+    /// Names of the grammars declared by the manifest.
+    pub fn grammar_names(&self) -> Vec<&String> {
+        match self {
+            ExtensionMetadata::TomlManifest(manifest)
+            | ExtensionMetadata::YamlManifest(manifest) => {
+                manifest.grammars.iter().flat_map(HashMap::keys).collect()
+            }
+            ExtensionMetadata::JsonManifest(manifest) => {
+                manifest.grammars.iter().flat_map(HashMap::keys).collect()
+            }
+        }
+    }
+
+    /// Whether the manifest declares a grammar with this name.
+    pub fn declares_grammar(&self, name: &str) -> bool {
+        self.grammar_names().iter().any(|grammar| *grammar == name)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -122,6 +224,167 @@ pub struct ThemeExtension {
 pub enum Theme {
     V1(Option<themes_v1_schema::ThemeFamilyContent>),
     V2(Option<themes_v2_schema::ThemeFamilyContent>),
+    /// Only available when the v0.3.0 schema was found at build time.
+    #[cfg(themes_v3)]
+    V3(Option<themes_v3_schema::ThemeFamilyContent>),
+    /// A theme file that couldn't be parsed with any known schema.
+    Invalid(PathBuf),
+}
+
+/// Appearance of an individual theme in a theme family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Appearance {
+    Light,
+    Dark,
+}
+
+impl Theme {
+    /// Whether the theme file was parsed with a known schema.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Theme::V1(Some(_)) | Theme::V2(Some(_)) => true,
+            #[cfg(themes_v3)]
+            Theme::V3(Some(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Appearances of each individual theme in the theme family.
+    pub fn appearances(&self) -> Vec<Appearance> {
+        match self {
+            Theme::V1(Some(family)) => family
+                .themes
+                .iter()
+                .map(|theme| match theme.appearance {
+                    themes_v1_schema::AppearanceContent::Light => Appearance::Light,
+                    themes_v1_schema::AppearanceContent::Dark => Appearance::Dark,
+                })
+                .collect(),
+            Theme::V2(Some(family)) => family
+                .themes
+                .iter()
+                .map(|theme| match theme.appearance {
+                    themes_v2_schema::AppearanceContent::Light => Appearance::Light,
+                    themes_v2_schema::AppearanceContent::Dark => Appearance::Dark,
+                })
+                .collect(),
+            #[cfg(themes_v3)]
+            Theme::V3(Some(family)) => family
+                .themes
+                .iter()
+                .map(|theme| match theme.appearance {
+                    themes_v3_schema::AppearanceContent::Light => Appearance::Light,
+                    themes_v3_schema::AppearanceContent::Dark => Appearance::Dark,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Colors of the syntax styles of each theme in the theme family, as
+    /// `(theme name, capture, color)`.
+    pub fn syntax_colors(&self) -> Vec<(String, String, String)> {
+        match self {
+            Theme::V1(Some(family)) => family
+                .themes
+                .iter()
+                .flat_map(|theme| {
+                    theme.style.syntax.iter().flat_map(move |(capture, style)| {
+                        [&style.color, &style.background_color]
+                            .into_iter()
+                            .flatten()
+                            .map(move |color| (theme.name.clone(), capture.clone(), color.clone()))
+                    })
+                })
+                .collect(),
+            Theme::V2(Some(family)) => family
+                .themes
+                .iter()
+                .flat_map(|theme| {
+                    theme.style.syntax.iter().flat_map(move |(capture, style)| {
+                        [&style.color, &style.background_color]
+                            .into_iter()
+                            .flatten()
+                            .map(move |color| (theme.name.clone(), capture.clone(), color.clone()))
+                    })
+                })
+                .collect(),
+            #[cfg(themes_v3)]
+            Theme::V3(Some(family)) => family
+                .themes
+                .iter()
+                .flat_map(|theme| {
+                    theme.style.syntax.iter().flat_map(move |(capture, style)| {
+                        [&style.color, &style.background_color]
+                            .into_iter()
+                            .flatten()
+                            .map(move |color| (theme.name.clone(), capture.clone(), color.clone()))
+                    })
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Syntax styles of each theme in the theme family that don't set any color or font property,
+    /// as `(theme name, capture)`.
+    pub fn empty_syntax_captures(&self) -> Vec<(String, String)> {
+        match self {
+            Theme::V1(Some(family)) => family
+                .themes
+                .iter()
+                .flat_map(|theme| {
+                    theme
+                        .style
+                        .syntax
+                        .iter()
+                        .filter(|(_, style)| {
+                            style.color.as_deref().is_none_or(str::is_empty)
+                                && style.background_color.as_deref().is_none_or(str::is_empty)
+                                && style.font_style.is_none()
+                                && style.font_weight.is_none()
+                        })
+                        .map(|(capture, _)| (theme.name.clone(), capture.clone()))
+                })
+                .collect(),
+            Theme::V2(Some(family)) => family
+                .themes
+                .iter()
+                .flat_map(|theme| {
+                    theme
+                        .style
+                        .syntax
+                        .iter()
+                        .filter(|(_, style)| {
+                            style.color.as_deref().is_none_or(str::is_empty)
+                                && style.background_color.as_deref().is_none_or(str::is_empty)
+                                && style.font_style.is_none()
+                                && style.font_weight.is_none()
+                        })
+                        .map(|(capture, _)| (theme.name.clone(), capture.clone()))
+                })
+                .collect(),
+            #[cfg(themes_v3)]
+            Theme::V3(Some(family)) => family
+                .themes
+                .iter()
+                .flat_map(|theme| {
+                    theme
+                        .style
+                        .syntax
+                        .iter()
+                        .filter(|(_, style)| {
+                            style.color.as_deref().is_none_or(str::is_empty)
+                                && style.background_color.as_deref().is_none_or(str::is_empty)
+                                && style.font_style.is_none()
+                                && style.font_weight.is_none()
+                        })
+                        .map(|(capture, _)| (theme.name.clone(), capture.clone()))
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 /// Basic struct for a JSON schema to check the schema version.
@@ -131,7 +394,88 @@ pub struct JsonSchema {
     pub schema: String,
 }
 
+/// Number of style properties that survived deserializing a theme family. Properties a schema
+/// doesn't know are dropped, so the schema keeping more of them describes the theme better.
+fn recognized_style_keys<T: Serialize>(theme_family: &T) -> usize {
+    let Ok(value) = serde_json_lenient::to_value(theme_family) else {
+        return 0;
+    };
+
+    value
+        .get("themes")
+        .and_then(|themes| themes.as_array())
+        .map_or(0, |themes| {
+            themes
+                .iter()
+                .filter_map(|theme| theme.get("style")?.as_object())
+                .map(|style| style.values().filter(|value| !value.is_null()).count())
+                .sum()
+        })
+}
+
+/// Parse a theme family without a known `$schema`. Every schema accepts most themes, so parse
+/// success alone would pick the oldest one; instead the schema recognizing the most of the theme's
+/// styles wins, with ties going to the oldest schema. Returns `None` if no schema accepts it.
+fn parse_unversioned_theme(contents: &str) -> Option<Theme> {
+    let mut candidates: Vec<(usize, Theme)> = Vec::new();
+
+    if let Ok(v1) = serde_json_lenient::from_str::<themes_v1_schema::ThemeFamilyContent>(contents) {
+        candidates.push((recognized_style_keys(&v1), Theme::V1(Some(v1))));
+    }
+    if let Ok(v2) = serde_json_lenient::from_str::<themes_v2_schema::ThemeFamilyContent>(contents) {
+        candidates.push((recognized_style_keys(&v2), Theme::V2(Some(v2))));
+    }
+    #[cfg(themes_v3)]
+    if let Ok(v3) = serde_json_lenient::from_str::<themes_v3_schema::ThemeFamilyContent>(contents) {
+        candidates.push((recognized_style_keys(&v3), Theme::V3(Some(v3))));
+    }
+
+    candidates
+        .into_iter()
+        .reduce(|best, candidate| {
+            if candidate.0 > best.0 {
+                candidate
+            } else {
+                best
+            }
+        })
+        .map(|(_, theme)| theme)
+}
+
 impl ThemeExtension {
+    /// Sorted, deduplicated syntax capture names styled across all themes in the extension.
+    pub fn syntax_capture_names(&self) -> Vec<String> {
+        let mut syntax_captures: Vec<String> = self
+            .themes
+            .iter()
+            .flat_map(|theme| match theme {
+                Some(Theme::V1(Some(theme))) => theme
+                    .themes
+                    .iter()
+                    .flat_map(|theme| theme.style.syntax.keys())
+                    .collect::<Vec<&String>>(),
+                Some(Theme::V2(Some(theme))) => theme
+                    .themes
+                    .iter()
+                    .flat_map(|theme| theme.style.syntax.keys())
+                    .collect::<Vec<&String>>(),
+                #[cfg(themes_v3)]
+                Some(Theme::V3(Some(theme))) => theme
+                    .themes
+                    .iter()
+                    .flat_map(|theme| theme.style.syntax.keys())
+                    .collect::<Vec<&String>>(),
+                _ => Vec::new(),
+            })
+            .cloned()
+            .collect();
+
+        syntax_captures.sort_unstable();
+        syntax_captures.dedup();
+
+        syntax_captures
+    }
+
     pub fn from_scan(themes_dir: &PathBuf) -> Result<Self> {
         let mut themes: Vec<Option<Theme>> = Vec::new();
 
@@ -147,40 +491,66 @@ impl ThemeExtension {
                     Some(json)
                         if json.schema.as_str() == "https://zed.dev/schema/themes/v0.1.0.json" =>
                     {
-                        Some(Theme::V1(
+                        Some(
                             serde_json_lenient::from_str::<themes_v1_schema::ThemeFamilyContent>(
                                 &contents,
                             )
-                            .map_err(|e| {
-                                warn!("Error parsing v1 theme: {}", e);
-                            })
-                            .ok(),
-                        ))
+                            .map_or_else(
+                                |e| {
+                                    warn!("Error parsing v1 theme: {}", e);
+                                    Theme::Invalid(path.clone())
+                                },
+                                |theme| Theme::V1(Some(theme)),
+                            ),
+                        )
                     }
                     Some(json)
                         if json.schema.as_str() == "https://zed.dev/schema/themes/v0.2.0.json" =>
                     {
-                        Some(Theme::V2(
+                        Some(
                             serde_json_lenient::from_str::<themes_v2_schema::ThemeFamilyContent>(
                                 &contents,
                             )
-                            .map_err(|e| {
-                                warn!("Error parsing v2 theme: {}", e);
-                            })
-                            .ok(),
-                        ))
+                            .map_or_else(
+                                |e| {
+                                    warn!("Error parsing v2 theme: {}", e);
+                                    Theme::Invalid(path.clone())
+                                },
+                                |theme| Theme::V2(Some(theme)),
+                            ),
+                        )
+                    }
+                    #[cfg(themes_v3)]
+                    Some(json)
+                        if json.schema.as_str() == "https://zed.dev/schema/themes/v0.3.0.json" =>
+                    {
+                        Some(
+                            serde_json_lenient::from_str::<themes_v3_schema::ThemeFamilyContent>(
+                                &contents,
+                            )
+                            .map_or_else(
+                                |e| {
+                                    warn!("Error parsing v3 theme: {}", e);
+                                    Theme::Invalid(path.clone())
+                                },
+                                |theme| Theme::V3(Some(theme)),
+                            ),
+                        )
                     }
-                    _ => match serde_json_lenient::from_str(&contents) {
-                        Ok(v1) => Some(Theme::V1(Some(v1))),
-                        Err(_) => {
-                            if let Ok(v2) = serde_json_lenient::from_str(&contents) {
-                                Some(Theme::V2(Some(v2)))
-                            } else {
-                                warn!("Error parsing theme: {}", path.to_string_lossy());
-                                None
-                            }
+                    other => {
+                        if let Some(json) = other {
+                            warn!(
+                                "theme {} declares unknown $schema '{}'",
+                                path.display(),
+                                json.schema
+                            );
                         }
-                    },
+
+                        Some(parse_unversioned_theme(&contents).unwrap_or_else(|| {
+                            warn!("Error parsing theme: {}", path.to_string_lossy());
+                            Theme::Invalid(path.clone())
+                        }))
+                    }
                 };
 
                 themes.push(theme_family_content);
@@ -204,6 +574,21 @@ pub struct Language {
     pub folds_queries: Option<String>,
     pub outline_queries: Option<String>,
     pub brackets_queries: Option<String>,
+    /// Captures extracted from the query files during the scan, so analysis doesn't have to
+    /// re-parse them. Missing from scans cached before captures were stored.
+    #[serde(default)]
+    pub captures: Option<QueryCaptures>,
+}
+
+/// Capture names (including repeats) of each query file of a [`Language`], or `None` where the
+/// query file is missing or couldn't be parsed.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct QueryCaptures {
+    pub highlights: Option<Vec<String>>,
+    pub injections: Option<Vec<String>>,
+    pub folds: Option<Vec<String>>,
+    pub outline: Option<Vec<String>>,
+    pub brackets: Option<Vec<String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -218,7 +603,6 @@ pub struct LanguageConfig {
 }
 
 impl LanguageExtension {
-    // Handle `grammars/<lang>.toml` (e.g. assembly extention).
     pub fn from_scan(languages_dir: &PathBuf) -> Result<Self> {
         let mut languages: Vec<Language> = Vec::new();
 
@@ -234,7 +618,7 @@ impl LanguageExtension {
                 let mut outline_queries = None;
                 let mut brackets_queries = None;
 
-                for entry in fs::read_dir(path)? {
+                for entry in fs::read_dir(&path)? {
                     let entry = entry?;
                     let path = entry.path();
                     let file_name = entry.file_name();
@@ -253,13 +637,69 @@ impl LanguageExtension {
                     }
                 }
 
+                // Skip malformed (e.g. empty) language directories instead of failing the scan.
+                let Some(config) = config else {
+                    warn!(
+                        "skipping language directory {} without a config.toml",
+                        path.display()
+                    );
+                    continue;
+                };
+
                 languages.push(Language {
-                    config: config.expect("language configuration should exist"),
+                    config,
                     highlights_queries,
                     injections_queries,
                     folds_queries,
                     outline_queries,
                     brackets_queries,
+                    captures: None,
+                });
+            }
+        }
+
+        Ok(Self { languages })
+    }
+
+    /// Scan the `grammars/<lang>.toml` layout (e.g. assembly extension), where each grammar is
+    /// declared in its own TOML file rather than in a `languages/<lang>/config.toml`.
+    pub fn from_grammars_scan(grammars_dir: &PathBuf) -> Result<Self> {
+        let mut languages: Vec<Language> = Vec::new();
+
+        for entry in fs::read_dir(grammars_dir)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            if path.is_file() && path.extension().is_some_and(|e| e == "toml") {
+                let contents = fs::read_to_string(&path)?;
+                let config = toml::from_str::<LanguageConfig>(&contents).unwrap_or_else(|_| {
+                    // Grammar files usually only declare a repository and commit, so fall back to
+                    // the file name for the language and grammar names.
+                    let name = path
+                        .file_stem()
+                        .expect("grammar file should have a name")
+                        .to_string_lossy()
+                        .to_string();
+
+                    LanguageConfig {
+                        name: name.clone(),
+                        grammar: name,
+                        path_suffixes: None,
+                        line_comments: None,
+                        tab_size: None,
+                        hard_tabs: None,
+                        first_line_pattern: None,
+                    }
+                });
+
+                languages.push(Language {
+                    config,
+                    highlights_queries: None,
+                    injections_queries: None,
+                    folds_queries: None,
+                    outline_queries: None,
+                    brackets_queries: None,
+                    captures: None,
                 });
             }
         }
@@ -267,3 +707,92 @@ impl LanguageExtension {
         Ok(Self { languages })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_display_contains_id_and_type_tag() {
+        let extension = Extension {
+            id: "test-language".to_string(),
+            metadata: ExtensionMetadata::TomlManifest(
+                toml::from_str(
+                    r#"
+                        name = "Test Language"
+                        version = "0.1.0"
+                        authors = ["Zeta"]
+                        repository = "https://github.com/zeta/test-language"
+                    "#,
+                )
+                .unwrap(),
+            ),
+            builtin: false,
+            git_provider: None,
+            git_url: None,
+            commit: None,
+            r#type: ExtensionType::Language(LanguageExtension::default()),
+        };
+
+        let rendered = extension.to_string();
+        assert!(rendered.contains("test-language"));
+        assert!(rendered.contains("[language]"));
+        assert!(!rendered.contains('\n'));
+    }
+
+    /// Style properties of `ThemeStyleContent` that take a string, according to a schema.
+    fn string_style_properties(schema: &str) -> Vec<String> {
+        let schema: serde_json_lenient::Value = serde_json_lenient::from_str(schema).unwrap();
+        let properties = schema["definitions"]["ThemeStyleContent"]["properties"]
+            .as_object()
+            .expect("schema should define ThemeStyleContent properties");
+
+        properties
+            .iter()
+            .filter(|(_, property)| match &property["type"] {
+                serde_json_lenient::Value::String(r#type) => r#type == "string",
+                serde_json_lenient::Value::Array(types) => {
+                    types.iter().any(|r#type| r#type == "string")
+                }
+                _ => false,
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn unversioned_v2_theme_parses_as_v2() {
+        let v1_properties = string_style_properties(include_str!(concat!(
+            env!("OUT_DIR"),
+            "/themes-v1.schema.json"
+        )));
+        let v2_only_property = string_style_properties(include_str!(concat!(
+            env!("OUT_DIR"),
+            "/themes-v2.schema.json"
+        )))
+        .into_iter()
+        .find(|property| !v1_properties.contains(property))
+        .expect("v2 schema should add a string style property");
+
+        // No `$schema`, and a style property only the v2 schema knows.
+        let theme = serde_json_lenient::json!({
+            "name": "Test",
+            "author": "Zeta",
+            "themes": [{
+                "name": "Test Dark",
+                "appearance": "dark",
+                "style": { v2_only_property: "#ffffff", "syntax": {} }
+            }]
+        });
+
+        assert!(matches!(
+            parse_unversioned_theme(&theme.to_string()),
+            Some(Theme::V2(Some(_)))
+        ));
+        assert!(matches!(
+            parse_unversioned_theme(r#"{ "name": "Test", "author": "Zeta", "themes": [] }"#),
+            Some(Theme::V1(Some(_)))
+        ));
+        assert!(parse_unversioned_theme("not a theme").is_none());
+    }
+}
@@ -0,0 +1,5 @@
+(comment) @comment
+(string) @string
+(identifier) @variable
+((identifier) @_private
+  (#eq? @_private "self"))